@@ -3,7 +3,7 @@ use std::fs::create_dir_all;
 
 use cosmwasm_schema::{export_schema, remove_schemas, schema_for};
 
-use cw_secret_share_documents::msg::{ExecuteMsg, ExecutePermitMsg, InstantiateMsg, QueryMsg};
+use cw_secret_share_documents::msg::{ExecuteMsg, ExecutePermitMsg, InstantiateMsg, MigrateMsg, QueryMsg};
 
 fn main() {
     let mut out_dir = current_dir().unwrap();
@@ -12,6 +12,7 @@ fn main() {
     remove_schemas(&out_dir).unwrap();
 
     export_schema(&schema_for!(InstantiateMsg), &out_dir);
+    export_schema(&schema_for!(MigrateMsg), &out_dir);
     export_schema(&schema_for!(ExecuteMsg), &out_dir);
     export_schema(&schema_for!(ExecutePermitMsg), &out_dir);
     export_schema(&schema_for!(QueryMsg), &out_dir);
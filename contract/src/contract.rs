@@ -1,8 +1,8 @@
 
 use bech32::{ToBase32, Variant};
 use cosmwasm_std::{
-    entry_point, to_binary, Addr, Binary, Deps, DepsMut, Env, MessageInfo, Response, StdError,
-    StdResult,
+    entry_point, to_binary, Addr, Binary, BlockInfo, Deps, DepsMut, Env, MessageInfo, Response, 
+    StdError, StdResult, Storage, Timestamp,
 };
 
 
@@ -15,20 +15,28 @@ use cosmwasm_storage::ReadonlyPrefixedStorage;
 
 use secret_toolkit::permit::{pubkey_to_account, Permit, RevokedPermits, SignedPermit};
 use secret_toolkit::serialization::{Json, Serde};
+use secret_toolkit::viewing_key::{ViewingKey, ViewingKeyStore};
 use serde::Serialize;
 use sha3;
 
 use crate::error::ContractError;
 use crate::msg::{
-    ContractKeyResponse, EncryptedExecuteMsg, ExecuteMsg, ExecuteMsgAction, 
-    ExecutePermitMsg, FileAccessResponse, FileIdsResponse, FilePayloadResponse, 
-    InstantiateMsg, QueryMsg, QueryWithPermit
+    AccessRequestResponse, AccessRequestsResponse, AllowedOriginsResponse, AllowedSource, ContractKeyResponse, 
+    ContractStatusResponse, DocumentPermission, EncryptedExecuteMsg, EncryptedQueryResponse, ExecuteMsg, ExecuteMsgAction, ExecutePermitMsg, FileAccessResponse, 
+    FileIdsResponse, FileMetadataResponse, FilePayloadResponse, FileVersionInfo, FileVersionResponse, FileVersionsResponse, FolderIdsResponse, FolderResponse, GroupIdsResponse, GroupResponse, 
+    InstantiateMsg, MigrateMsg, QueryMsg, QueryWithPermit, RevokedPermitsResponse, RoleAssignment, SignatureScheme, ViewingKeyResponse, 
+    WrappedFileKey
 };
 
 use crate::state::{
-    load, may_load, save, Config, ContractKeys, FileMetadata, FileState, 
-    UserInfo, CONFIG, CONTRACT_KEYS, FILE_PERMISSIONS, PREFIX_FILES, 
-    PREFIX_FILES_METADATA, PREFIX_REVOKED_PERMITS, PREFIX_USERS
+    load, may_load, remove, save, AccessRequest, AccessRequestStatus, ClaimCode, Config, ContractKeys, Expiration, FileMetadata, FilePermission, 
+    FileInfo, FileRole, FileState, FileVersion, Folder, Group, PreviousContractKeys, UserInfo, CONFIG, CONTRACT_KEYS, DELETED_FILES, FILE_KEYS, FILE_PERMISSIONS, FILE_VERSIONS, FOLDERS, GROUPS, 
+    LEGACY_CONFIG, LEGACY_CONTRACT_KEYS, LEGACY_FILE_PERMISSIONS, LEGACY_PERMISSIONS_LEFT, PENDING_OWNERS, PREVIOUS_CONTRACT_KEYS, PREFIX_FILES, PREFIX_FILES_METADATA, PREFIX_REVOKED_PERMITS, PREFIX_USERS, 
+    USER_FOLDERS, USER_GROUPS, ACCESS_REQUESTS, ALLOWED_RELAYERS, ALLOWED_SOURCES, CLAIM_CODES, DELETED_FILE_VIEWERS, ETHEREUM_ADDRESSES, FILE_CLAIM_CODES, 
+    ID_SALT, PROCESSED_MESSAGES, STORAGE_VERSION, USER_REVOKED_PERMITS, CURRENT_STORAGE_VERSION, 
+    MAX_ACCESS_REQUEST_NOTE_LENGTH, MAX_FILE_MIME_TYPE_LENGTH, MAX_FILE_NAME_LENGTH, MAX_FILE_TAGS, MAX_FILE_TAG_LENGTH, 
+    MAX_FOLDER_DEPTH, MAX_FOLDER_FILES, MAX_FOLDER_NAME_LENGTH, MAX_FOLDER_SUB_FOLDERS, MAX_GROUP_MEMBERS, MAX_GROUP_NAME_LENGTH, 
+    PENDING_ACCESS_REQUESTS
 };
 
 use cosmwasm_storage::PrefixedStorage;
@@ -46,37 +54,44 @@ use hex;
 /// Generate a pair of public/private key for the contract. This key will be use
 /// to exchange message safely from polygon chain to the secret network. (Secret 
 /// As A Service).
+///
+/// The admin of the contract is the given one, or the sender of the message.
 #[entry_point]
 pub fn instantiate(
     deps: DepsMut,
     env: Env,
     info: MessageInfo,
-    _msg: InstantiateMsg,
+    msg: InstantiateMsg,
 ) -> Result<Response, StdError> {
     // Create the public/private keys for the contract
     let rng = env.block.random.unwrap().0;
-    let secp = Secp256k1::new();
-
-    let private_key = SecretKey::from_slice(&rng).unwrap();
-    let private_key_string = private_key.display_secret().to_string();
-    let private_key_bytes = hex::decode(private_key_string).unwrap();
+    let my_keys = _generate_contract_keys(&rng, 0)?;
 
-    let public_key = PublicKey::from_secret_key(&secp, &private_key);
-    let public_key_bytes = public_key.serialize().to_vec();
+    CONTRACT_KEYS.save(deps.storage, &my_keys)?;
 
-    let my_keys = ContractKeys {
-        private_key: private_key_bytes,
-        public_key: public_key_bytes,
-    };
+    // Seed used to generate the viewing keys of the users
+    let viewing_key_seed = Sha256::digest([rng.as_slice(), b"viewing_key".as_slice()].concat());
+    ViewingKey::set_seed(deps.storage, &viewing_key_seed);
 
-    CONTRACT_KEYS.save(deps.storage, &my_keys)?;
+    // Salt keeping the ids unpredictable, even without the randomness of a block
+    let id_salt: [u8; 32] = Sha256::digest([rng.as_slice(), b"id_salt".as_slice()].concat()).into();
+    ID_SALT.save(deps.storage, &id_salt)?;
 
     // Save the configuration
+    let admin = match msg.admin {
+        Some(admin) => deps.api.addr_validate(admin.as_str())?,
+        None => info.sender.clone(),
+    };
     CONFIG.save(deps.storage, &Config {
         contract_address: env.contract.address,
-        index: 0
+        index: 0,
+        admin,
+        writes_paused: false,
+        reads_paused: false,
     })?;
 
+    STORAGE_VERSION.save(deps.storage, &CURRENT_STORAGE_VERSION)?;
+
     deps.api
         .debug(&format!("Contract was initialized by {}", info.sender));
 
@@ -84,35 +99,405 @@ pub fn instantiate(
 }
 
 
+/// Migrate the storage of a contract instantiated with a previous version.
+///
+/// The version of the storage is saved since the admin was added. Without it,
+/// the contract comes from the first version: its configuration had no admin
+/// nor pause flags. The admin must be given, the contract is not paused once
+/// migrated. The keys of the contract get the version 0, a secret salt is created
+/// for the ids and a seed for the viewing keys, and the boolean accesses become a
+/// viewer role, or the owner role for the owner.
+///
+/// Converting an access costs a read and a write, so the gas grows with the number
+/// of accesses. With a `limit`, only that many accesses are converted and the
+/// migration is resumed by the next calls, from the last access to the first one:
+/// removing an access moves the last one in its place, already converted. Until
+/// the last call, the contract is paused, as the accesses are partly converted.
+#[entry_point]
+pub fn migrate(
+    mut deps: DepsMut,
+    env: Env,
+    msg: MigrateMsg,
+) -> Result<Response, ContractError> {
+
+    let storage_version = STORAGE_VERSION.may_load(deps.storage)?.unwrap_or(0);
+
+    if storage_version < 1 {
+        // The first call converts the configuration and the keys, the next ones resume
+        // the conversion of the accesses
+        let permissions_left = match LEGACY_PERMISSIONS_LEFT.may_load(deps.storage)? {
+            Some(permissions_left) => permissions_left,
+            None => _migrate_legacy_config(deps.branch(), &env, msg.admin)?,
+        };
+
+        // The permissions were a boolean access, given to the owner as well
+        let converted_from = permissions_left.saturating_sub(msg.limit.unwrap_or(u32::MAX));
+        for position in (converted_from..permissions_left).rev() {
+            let (file_key, user) = match LEGACY_FILE_PERMISSIONS.paging_keys(deps.storage, position, 1)?.pop() {
+                Some(key) => key,
+                None => continue,
+            };
+
+            match LEGACY_FILE_PERMISSIONS.get(deps.storage, &(file_key, user.clone())) {
+                Some(true) => {},
+                Some(false) => {
+                    FILE_PERMISSIONS.remove(deps.storage, &(file_key, user))?;
+                    continue;
+                },
+                None => continue,
+            }
+
+            let file_metadata_store = ReadonlyPrefixedStorage::new(deps.storage, PREFIX_FILES_METADATA);
+            let loaded_metadata: Option<FileMetadata> = may_load(&file_metadata_store, &file_key)?;
+            let role = match loaded_metadata {
+                Some(metadata) if metadata.owner == user => FileRole::Owner,
+                _ => FileRole::Viewer,
+            };
+
+            let permission = FilePermission {
+                role,
+                expires_at: None,
+            };
+            FILE_PERMISSIONS.insert(deps.storage, &(file_key, user), &permission)?;
+        }
+
+        if converted_from > 0 {
+            LEGACY_PERMISSIONS_LEFT.save(deps.storage, &converted_from)?;
+            return Ok(Response::default());
+        }
+
+        // All the accesses are converted, the contract can be used
+        LEGACY_PERMISSIONS_LEFT.remove(deps.storage);
+        let config = CONFIG.load(deps.storage)?;
+        CONFIG.save(deps.storage, &Config { writes_paused: false, reads_paused: false, ..config })?;
+    }
+
+    STORAGE_VERSION.save(deps.storage, &CURRENT_STORAGE_VERSION)?;
+
+    Ok(Response::default())
+}
+
+
+/// Convert the configuration and the keys of the first version of the contract,
+/// and return the number of boolean accesses to convert.
+///
+/// The contract stays paused until all the accesses are converted.
+fn _migrate_legacy_config(deps: DepsMut, env: &Env, admin: Option<Addr>) -> Result<u32, ContractError> {
+    let admin = match admin {
+        Some(admin) => deps.api.addr_validate(admin.as_str())?,
+        None => return Err(ContractError::CustomError { val: String::from("An admin is required to migrate the contract") }),
+    };
+    let rng = match &env.block.random {
+        Some(random) => random.0.clone(),
+        None => return Err(ContractError::CustomError { val: String::from("The randomness of the block is required to migrate the contract") }),
+    };
+
+    let legacy_config = LEGACY_CONFIG.load(deps.storage)?;
+    CONFIG.save(deps.storage, &Config {
+        contract_address: legacy_config.contract_address,
+        index: legacy_config.index,
+        admin,
+        writes_paused: true,
+        reads_paused: true,
+    })?;
+
+    // The viewing keys did not exist
+    let viewing_key_seed = Sha256::digest([rng.as_slice(), b"viewing_key".as_slice()].concat());
+    ViewingKey::set_seed(deps.storage, &viewing_key_seed);
+
+    // The ids were the hash of the counter, the next ones are salted
+    let id_salt: [u8; 32] = Sha256::digest([rng.as_slice(), b"id_salt".as_slice()].concat()).into();
+    ID_SALT.save(deps.storage, &id_salt)?;
+
+    // The keys of the contract had no version
+    let legacy_keys = LEGACY_CONTRACT_KEYS.load(deps.storage)?;
+    CONTRACT_KEYS.save(deps.storage, &ContractKeys {
+        private_key: legacy_keys.private_key,
+        public_key: legacy_keys.public_key,
+        version: 0,
+    })?;
+
+    Ok(LEGACY_FILE_PERMISSIONS.get_len(deps.storage)?)
+}
+
+
+/// Create a public/private key pair for the contract from a random seed.
+fn _generate_contract_keys(seed: &[u8], version: u32) -> StdResult<ContractKeys> {
+    let secp = Secp256k1::new();
+
+    let private_key = SecretKey::from_slice(seed)
+        .map_err(|e| StdError::generic_err(e.to_string()))?;
+    let private_key_string = private_key.display_secret().to_string();
+    let private_key_bytes = hex::decode(private_key_string).unwrap();
+
+    let public_key = PublicKey::from_secret_key(&secp, &private_key);
+    let public_key_bytes = public_key.serialize().to_vec();
+
+    Ok(ContractKeys {
+        private_key: private_key_bytes,
+        public_key: public_key_bytes,
+        version,
+    })
+}
+
+
 /// Execute function of the Smart Contract
 ///
 /// As we are using Secret As A Service, we should only receive EVM message 
-/// sent from Polygon via Axelar.
+/// sent from Polygon via Axelar. The relayer and the source of the message are
+/// checked against an allowlist. The admin messages are sent directly, as well
+/// as the actions of Secret Network users, authenticated by the sender.
 #[entry_point]
 pub fn execute(
     deps: DepsMut,
-    _env: Env,
-    _info: MessageInfo,
+    env: Env,
+    info: MessageInfo,
     msg: ExecuteMsg,
 ) -> Result<Response, ContractError> {
+
+    let config = CONFIG.load(deps.storage)?;
+
+    let is_admin_msg = matches!(
+        msg, 
+        ExecuteMsg::PauseWrites { .. } 
+        | ExecuteMsg::PauseReads { .. } 
+        | ExecuteMsg::ChangeAdmin { .. } 
+        | ExecuteMsg::UpdateAllowedSources { .. } 
+        | ExecuteMsg::UpdateAllowedRelayers { .. }
+        | ExecuteMsg::RotateContractKey { .. }
+        | ExecuteMsg::PruneProcessedMessages { .. }
+    );
+
+    // Allow the admin to pause the contract in case of emergency (bug, compromised
+    // key...), to give the admin role to someone else, to manage the origins
+    // allowed to send messages, to rotate the keys of the contract and to prune
+    // the expired processed messages.
+    if is_admin_msg && info.sender != config.admin {
+        return Err(ContractError::UnauthorizedAdmin)
+    }
+
+    // No user message is executed while the contract is paused, except the
+    // revocations of permits. The encrypted messages are checked once decrypted.
+    let is_revocation = matches!(
+        msg,
+        ExecuteMsg::RevokePermit { .. }
+        | ExecuteMsg::Direct { execute: ExecuteMsgAction::RevokePermit { .. } }
+    );
+    let is_encrypted = matches!(
+        msg,
+        ExecuteMsg::ReceiveMessageEvm { .. }
+        | ExecuteMsg::ReceiveMessageCosmos { .. }
+    );
+    if !is_admin_msg && !is_revocation && !is_encrypted && config.writes_paused {
+        return Err(ContractError::ContractPaused)
+    }
+
+    // Verify the origin of the message before decrypting anything
+    match &msg {
+        ExecuteMsg::ReceiveMessageEvm { source_chain, source_address, .. } 
+        | ExecuteMsg::ReceiveMessageCosmos { source_chain, source_address, .. } => {
+            _verify_origin(deps.storage, &info.sender, source_chain, source_address)?;
+        },
+        _ => {},
+    };
+
     match msg {
         ExecuteMsg::ReceiveMessageEvm {
             source_chain,
             source_address,
             payload,
-        } => receive_message_evm(deps, source_chain, source_address, payload),
+        } => receive_message_evm(deps, env, source_chain, source_address, payload),
         ExecuteMsg::ReceiveMessageCosmos {
             source_chain,
             source_address,
             payload,
-        } => receive_message_cosmos(deps, source_chain, source_address, payload),
+        } => receive_message_cosmos(deps, env, source_chain, source_address, payload),
+        ExecuteMsg::PauseWrites { paused } => {
+            CONFIG.save(deps.storage, &Config { writes_paused: paused, ..config })?;
+            Ok(Response::default())
+        },
+        ExecuteMsg::PauseReads { paused } => {
+            CONFIG.save(deps.storage, &Config { reads_paused: paused, ..config })?;
+            Ok(Response::default())
+        },
+        ExecuteMsg::ChangeAdmin { new_admin } => {
+            let new_admin = deps.api.addr_validate(new_admin.as_str())?;
+            CONFIG.save(deps.storage, &Config { admin: new_admin, ..config })?;
+            Ok(Response::default())
+        },
+        ExecuteMsg::UpdateAllowedSources { add, remove } => {
+            for source in add {
+                ALLOWED_SOURCES.insert(deps.storage, &_normalize_source(&source.source_chain, &source.source_address), &true)?;
+            }
+            for source in remove {
+                ALLOWED_SOURCES.remove(deps.storage, &_normalize_source(&source.source_chain, &source.source_address))?;
+            }
+            Ok(Response::default())
+        },
+        ExecuteMsg::UpdateAllowedRelayers { add, remove } => {
+            for relayer in add {
+                ALLOWED_RELAYERS.insert(deps.storage, &relayer, &true)?;
+            }
+            for relayer in remove {
+                ALLOWED_RELAYERS.remove(deps.storage, &relayer)?;
+            }
+            Ok(Response::default())
+        },
+        ExecuteMsg::RotateContractKey { grace_period } => {
+            rotate_contract_key(deps.storage, &env, grace_period)?;
+            Ok(Response::default())
+        },
+        ExecuteMsg::PruneProcessedMessages { start_page, page_size } => {
+            prune_processed_messages(deps.storage, &env.block, start_page, page_size)?;
+            Ok(Response::default())
+        },
+        ExecuteMsg::RevokePermit { permit_name } => {
+            revoke_permit(deps, &info.sender, permit_name)?;
+            Ok(Response::default())
+        },
+        ExecuteMsg::Direct { execute } => execute_action(deps, env, info.sender, None, execute),
+        ExecuteMsg::CreateViewingKey { entropy } => {
+            let key = ViewingKey::create(deps.storage, &info, &env, info.sender.as_str(), entropy.as_bytes());
+            Ok(Response::new().set_data(to_binary(&ViewingKeyResponse { key })?))
+        },
+        ExecuteMsg::SetViewingKey { key } => {
+            ViewingKey::set(deps.storage, info.sender.as_str(), &key);
+            Ok(Response::default())
+        },
+    }
+}
+
+
+/// Replace the keys of the contract by a new key pair.
+///
+/// The previous keys are kept until the end of the grace period, so the messages
+/// encrypted before the rotation can still be executed. The keys whose grace 
+/// period has ended are deleted.
+fn rotate_contract_key(
+    storage: &mut dyn Storage, 
+    env: &Env, 
+    grace_period: u64
+) -> Result<(), ContractError> {
+
+    // End of the grace period, rejecting a period too long to be represented
+    let grace_period_end = grace_period.checked_mul(1_000_000_000)
+        .and_then(|nanos| env.block.time.nanos().checked_add(nanos))
+        .map(Timestamp::from_nanos)
+        .ok_or_else(|| ContractError::CustomError { val: String::from("The grace period is too long") })?;
+
+    let current_keys = CONTRACT_KEYS.load(storage)?;
+    let version = current_keys.version + 1;
+
+    // Derive a new seed from the randomness of the block
+    let rng = match &env.block.random {
+        Some(random) => random.0.clone(),
+        None => return Err(ContractError::CustomError { val: String::from("The randomness of the block is required to rotate the contract key") }),
+    };
+    let mut hasher = Sha256::new();
+    hasher.update(&rng);
+    hasher.update(b"contract_key");
+    hasher.update(version.to_be_bytes());
+    let seed: [u8; 32] = hasher.finalize().into();
+
+    // Delete the previous keys no longer accepted
+    let mut expired_versions: Vec<u32> = Vec::new();
+    for item in PREVIOUS_CONTRACT_KEYS.iter(storage)? {
+        let (previous_version, previous_keys) = item?;
+        if previous_keys.expiration.is_expired(&env.block) {
+            expired_versions.push(previous_version);
+        }
+    }
+    for previous_version in expired_versions {
+        PREVIOUS_CONTRACT_KEYS.remove(storage, &previous_version)?;
+    }
+
+    PREVIOUS_CONTRACT_KEYS.insert(storage, &current_keys.version, &PreviousContractKeys {
+        keys: current_keys,
+        expiration: Expiration::AtTime(grace_period_end),
+    })?;
+
+    CONTRACT_KEYS.save(storage, &_generate_contract_keys(&seed, version)?)?;
+
+    Ok(())
+}
+
+
+/// Delete the digests of the processed messages whose deadline has passed.
+///
+/// An expired message is rejected anyway, its digest is no longer needed. The
+/// messages sent without a deadline are kept forever.
+fn prune_processed_messages(
+    storage: &mut dyn Storage, 
+    block: &BlockInfo, 
+    start_page: u32, 
+    page_size: u32
+) -> StdResult<()> {
+
+    let expired_digests: Vec<[u8; 32]> = PROCESSED_MESSAGES.paging(storage, start_page, page_size)?
+        .into_iter()
+        .filter(|(_, deadline)| deadline.as_ref().is_some_and(|deadline| deadline.is_expired(block)))
+        .map(|(digest, _)| digest)
+        .collect();
+
+    for digest in expired_digests {
+        PROCESSED_MESSAGES.remove(storage, &digest)?;
+    }
+
+    Ok(())
+}
+
+
+/// Load the keys of the contract for the given version. The previous keys are
+/// only accepted during their grace period.
+fn _load_contract_keys(
+    storage: &dyn Storage, 
+    block: &BlockInfo, 
+    version: u32
+) -> Result<ContractKeys, ContractError> {
+
+    let current_keys = CONTRACT_KEYS.load(storage)?;
+    if current_keys.version == version {
+        return Ok(current_keys)
+    }
+
+    match PREVIOUS_CONTRACT_KEYS.get(storage, &version) {
+        Some(previous_keys) if !previous_keys.expiration.is_expired(block) => Ok(previous_keys.keys),
+        _ => Err(ContractError::InvalidContractKeyVersion { val: version }),
+    }
+}
+
+
+/// Normalize a source of message. EVM addresses are case insensitive.
+fn _normalize_source(source_chain: &String, source_address: &String) -> (String, String) {
+    (source_chain.to_lowercase(), source_address.to_lowercase())
+}
+
+
+/// Verify that the message is relayed by an allowed relayer and comes from an
+/// allowed source contract.
+fn _verify_origin(
+    storage: &dyn Storage, 
+    sender: &Addr, 
+    source_chain: &String, 
+    source_address: &String
+) -> Result<(), ContractError> {
+
+    if ALLOWED_RELAYERS.get(storage, sender).is_none() {
+        return Err(ContractError::UnauthorizedOrigin)
     }
+
+    if ALLOWED_SOURCES.get(storage, &_normalize_source(source_chain, source_address)).is_none() {
+        return Err(ContractError::UnauthorizedOrigin)
+    }
+
+    Ok(())
 }
 
 
 /// Decrypt and execute the message passed from EVM.
 pub fn receive_message_evm(
     deps: DepsMut,
+    env: Env,
     source_chain: String,
     source_address: String,
     payload: Binary,
@@ -129,24 +514,29 @@ pub fn receive_message_evm(
     let decryted_payload = Json::deserialize::<EncryptedExecuteMsg>(&decoded.as_bytes()).unwrap();
 
     // Execute message
-    execute_msg(deps, source_chain, source_address, decryted_payload)
+    execute_msg(deps, env, source_chain, source_address, decryted_payload)
 }
 
 pub fn receive_message_cosmos(
     deps: DepsMut,
+    env: Env,
     source_chain: String,
     source_address: String,
     payload: EncryptedExecuteMsg,
 ) -> Result<Response, ContractError> {
-    execute_msg(deps, source_chain, source_address, payload)
+    execute_msg(deps, env, source_chain, source_address, payload)
 }
 
 /// We are using permit here to prove the user identity. By passing a message through Axelar,
 /// the message will be execute by Axelar and not by the end user. Thus, to prove the 
 /// secret address, we need to use a permit mechanism allowing us to confirm the user 
 /// identity as it is the only one to generate a valid permit.
+///
+/// A message seen on the source chain could be sent again. To avoid it, we store
+/// a digest of each processed message and reject the ones already processed.
 pub fn execute_msg(
-    deps: DepsMut,
+    mut deps: DepsMut,
+    env: Env,
     _source_chain: String,
     _source_address: String,
     payload: EncryptedExecuteMsg
@@ -154,14 +544,42 @@ pub fn execute_msg(
 
     let user_public_key = payload.public_key;
     let encrypted_data = payload.payload;
+    let contract_keys = _load_contract_keys(deps.storage, &env.block, payload.key_version)?;
+
+    // Reject a message already processed
+    let mut hasher = Sha256::new();
+    hasher.update(&user_public_key);
+    hasher.update(&encrypted_data);
+    let digest: [u8; 32] = hasher.finalize().into();
+
+    if PROCESSED_MESSAGES.get(deps.storage, &digest).is_some() {
+        return Err(ContractError::MessageAlreadyProcessed)
+    }
 
     // Decrypt the EVM message
-    let decrypt_msg = _decrypt_with_user_public_key(&deps, encrypted_data, user_public_key)?;
-    match decrypt_msg {
-        ExecutePermitMsg::WithPermit { permit, execute } => {
-            return execute_permit_message(deps, permit, execute);
+    let decrypt_msg = _decrypt_with_user_public_key(&contract_keys, encrypted_data, user_public_key)?;
+    let (response, deadline) = match decrypt_msg {
+        ExecutePermitMsg::WithPermit { permit, signature_scheme, execute, deadline } => {
+
+            // Reject a stale message
+            if deadline.is_some_and(|deadline| deadline.is_expired(&env.block)) {
+                return Err(ContractError::MessageExpired)
+            }
+
+            // A leaked permit can still be revoked while the contract is paused
+            let is_revocation = matches!(execute, ExecuteMsgAction::RevokePermit { .. });
+            if !is_revocation && CONFIG.load(deps.storage)?.writes_paused {
+                return Err(ContractError::ContractPaused)
+            }
+
+            (execute_permit_message(deps.branch(), env, permit, signature_scheme, execute)?, deadline)
         }
     };
+
+    // The deadline allows to prune the digest once the message has expired
+    PROCESSED_MESSAGES.insert(deps.storage, &digest, &deadline)?;
+
+    Ok(response)
 }
 
 fn to_binary_pretty<T>(data: &T) -> StdResult<Binary>
@@ -175,12 +593,86 @@ where
 }
 
 
+/// Name of a permission, as signed by the user.
+fn _permission_name(permission: &DocumentPermission) -> &'static str {
+    match permission {
+        DocumentPermission::List => "list",
+        DocumentPermission::Read => "read",
+        DocumentPermission::Write => "write",
+        DocumentPermission::Manage => "manage",
+        DocumentPermission::Transfer => "transfer",
+        DocumentPermission::Owner => "owner",
+    }
+}
+
+
+fn _keccak256(data: &[u8]) -> [u8; 32] {
+    let mut hasher = sha3::Keccak256::new();
+    hasher.update(data);
+    hasher.finalize().into()
+}
+
+
+/// Hash of the EIP-712 typed data of a permit.
+///
+/// The domain is tied to this contract and to the chain, so a permit cannot be
+/// used with another contract or on another chain:
+/// EIP712Domain(string name,string version,bytes32 salt)
+/// with salt = keccak256(chain_id ":" contract_address).
+///
+/// The permit params are signed as the typed struct:
+/// Permit(string permit_name,string chain_id,string[] allowed_tokens,string[] permissions)
+fn _eip712_permit_hash(
+    permit: &Permit<DocumentPermission>, 
+    chain_id: &str, 
+    contract_address: &str
+) -> [u8; 32] {
+
+    // Domain separator
+    let mut domain = vec![];
+    domain.extend_from_slice(&_keccak256(b"EIP712Domain(string name,string version,bytes32 salt)"));
+    domain.extend_from_slice(&_keccak256(b"Secret Share Documents"));
+    domain.extend_from_slice(&_keccak256(b"1"));
+    domain.extend_from_slice(&_keccak256(format!("{}:{}", chain_id, contract_address).as_bytes()));
+    let domain_separator = _keccak256(&domain);
+
+    // Arrays are encoded as the hash of the concatenation of their hashed elements
+    let hash_array = |items: Vec<&str>| -> [u8; 32] {
+        let hashes: Vec<u8> = items.iter().flat_map(|item| _keccak256(item.as_bytes())).collect();
+        _keccak256(&hashes)
+    };
+
+    let params = &permit.params;
+    let mut permit_struct = vec![];
+    permit_struct.extend_from_slice(&_keccak256(
+        b"Permit(string permit_name,string chain_id,string[] allowed_tokens,string[] permissions)"
+    ));
+    permit_struct.extend_from_slice(&_keccak256(params.permit_name.as_bytes()));
+    permit_struct.extend_from_slice(&_keccak256(params.chain_id.as_bytes()));
+    permit_struct.extend_from_slice(&hash_array(params.allowed_tokens.iter().map(|token| token.as_str()).collect()));
+    permit_struct.extend_from_slice(&hash_array(params.permissions.iter().map(_permission_name).collect()));
+    let permit_hash = _keccak256(&permit_struct);
+
+    let mut signed_bytes = vec![];
+    signed_bytes.extend_from_slice(b"\x19\x01");
+    signed_bytes.extend_from_slice(&domain_separator);
+    signed_bytes.extend_from_slice(&permit_hash);
+
+    _keccak256(&signed_bytes)
+}
+
+
 /// Verify the permit and check if it is the right users.
+///
+/// The permit can be signed with Metamask (Ethereum personal sign or EIP-712
+/// typed data) or with a Cosmos wallet as Keplr (amino).
 /// Returns: verified user address.
 fn _verify_permit(
     deps: Deps, 
-    permit: Permit, 
-    contract_address: Addr
+    permit: Permit<DocumentPermission>, 
+    signature_scheme: SignatureScheme,
+    contract_address: Addr,
+    chain_id: &str
 ) -> Result<Addr, ContractError> {
 
     let account_hrp = "secret";
@@ -220,19 +712,30 @@ fn _verify_permit(
 
     let user_data = &SignedPermit::from_params(&permit.params);
 
-    let mut signed_bytes = vec![];
-    signed_bytes.extend_from_slice(b"\x19Ethereum Signed Message:\n");
+    let signed_bytes_hash: [u8; 32] = match signature_scheme {
+        SignatureScheme::Metamask => {
+            let mut signed_bytes = vec![];
+            signed_bytes.extend_from_slice(b"\x19Ethereum Signed Message:\n");
 
-    let signed_tx_pretty_amino_json = to_binary_pretty(user_data)?;
+            let signed_tx_pretty_amino_json = to_binary_pretty(user_data)?;
 
-    signed_bytes.extend_from_slice(signed_tx_pretty_amino_json.len().to_string().as_bytes());
-    signed_bytes.extend_from_slice(signed_tx_pretty_amino_json.as_slice());
+            signed_bytes.extend_from_slice(signed_tx_pretty_amino_json.len().to_string().as_bytes());
+            signed_bytes.extend_from_slice(signed_tx_pretty_amino_json.as_slice());
 
-    let mut hasher = sha3::Keccak256::new();
+            let mut hasher = sha3::Keccak256::new();
+
+            hasher.update(&signed_bytes);
 
-    hasher.update(&signed_bytes);
+            hasher.finalize().into()
+        },
+        SignatureScheme::Amino => {
+            // The fields of the signed document are already sorted
+            let signed_tx_amino_json = to_binary(user_data)?;
 
-    let signed_bytes_hash = hasher.finalize();
+            Sha256::digest(signed_tx_amino_json.as_slice()).into()
+        },
+        SignatureScheme::Eip712 => _eip712_permit_hash(&permit, chain_id, &contract_address_str),
+    };
     
     let verified = deps
         .api
@@ -252,82 +755,630 @@ fn _verify_permit(
 }
 
 
+/// Check that the permit has the given permission.
+///
+/// The owner permission gives all the permissions.
+fn _check_permit_permission(
+    permit: &Permit<DocumentPermission>, 
+    permission: DocumentPermission
+) -> Result<(), ContractError> {
+
+    if permit.check_permission(&DocumentPermission::Owner) || permit.check_permission(&permission) {
+        return Ok(())
+    }
+
+    Err(ContractError::MissingPermitPermission { val: format!("{:?}", permission) })
+}
+
+
+/// Permission required by an action, if any.
+fn _action_permission(action: &ExecuteMsgAction) -> Option<DocumentPermission> {
+    match action {
+        ExecuteMsgAction::StoreNewFile { .. } 
+        | ExecuteMsgAction::RequestAccess { .. } 
+        | ExecuteMsgAction::ClaimAccess { .. } => Some(DocumentPermission::Write),
+        ExecuteMsgAction::ManageFileRights { .. } 
+        | ExecuteMsgAction::ApproveRequest { .. } 
+        | ExecuteMsgAction::DenyRequest { .. } 
+        | ExecuteMsgAction::RegisterClaimCode { .. } 
+        | ExecuteMsgAction::RevokeClaimCode { .. } 
+        | ExecuteMsgAction::CreateGroup { .. } 
+        | ExecuteMsgAction::UpdateGroupMembers { .. } 
+        | ExecuteMsgAction::CreateFolder { .. } 
+        | ExecuteMsgAction::ManageFolderRights { .. } 
+        | ExecuteMsgAction::MoveFile { .. } 
+        | ExecuteMsgAction::MoveFolder { .. } 
+        | ExecuteMsgAction::AddFileKeys { .. } => Some(DocumentPermission::Manage),
+        ExecuteMsgAction::UpdateFile { .. } 
+        | ExecuteMsgAction::UpdateFileInfo { .. } 
+        | ExecuteMsgAction::RekeyFile { .. } => Some(DocumentPermission::Write),
+        ExecuteMsgAction::ProposeOwnershipTransfer { .. } 
+        | ExecuteMsgAction::AcceptOwnership { .. } 
+        | ExecuteMsgAction::CancelOwnershipTransfer { .. } => Some(DocumentPermission::Transfer),
+        // Any permit of the user can revoke a permit
+        ExecuteMsgAction::RevokePermit { .. } => None,
+        ExecuteMsgAction::DeleteFile { .. } => Some(DocumentPermission::Owner),
+    }
+}
+
+
+/// Permission required by a query.
+fn _query_permission(query: &QueryWithPermit) -> DocumentPermission {
+    match query {
+        QueryWithPermit::GetFileContent { .. } 
+        | QueryWithPermit::GetFileVersions { .. } 
+        | QueryWithPermit::GetFileVersion { .. } => DocumentPermission::Read,
+        QueryWithPermit::GetFileAccess { .. } 
+        | QueryWithPermit::GetAccessRequests { .. } => DocumentPermission::Manage,
+        QueryWithPermit::GetFileIds { .. } 
+        | QueryWithPermit::GetAccessRequestStatus { .. } 
+        | QueryWithPermit::GetGroupIds { .. } 
+        | QueryWithPermit::GetGroup { .. } 
+        | QueryWithPermit::GetFolderIds { .. } 
+        | QueryWithPermit::GetFolder { .. } 
+        | QueryWithPermit::GetFileMetadata { .. } 
+        | QueryWithPermit::GetRevokedPermits { .. } => DocumentPermission::List,
+    }
+}
+
+
+/// Derive the Ethereum address of a user from the public key of his permit.
+///
+/// The address is the last 20 bytes of the keccak256 hash of the uncompressed
+/// public key (without its prefix), as a lowercase hex string.
+fn _ethereum_address(pubkey: &[u8]) -> Result<Addr, ContractError> {
+
+    let public_key = PublicKey::from_slice(pubkey)
+        .map_err(|e| {
+            ContractError::InvalidPublicKey { val: e.to_string() }
+        })?;
+    let uncompressed_public_key = public_key.serialize_uncompressed();
+
+    let mut hasher = sha3::Keccak256::new();
+    hasher.update(&uncompressed_public_key[1..]);
+    let hash = hasher.finalize();
+
+    Ok(Addr::unchecked(format!("0x{}", hex::encode(&hash[12..]))))
+}
+
+
+/// Normalize an address given by a user.
+///
+/// Ethereum addresses can be given with a checksum (mixed case), we store them
+/// in lowercase.
+fn _normalize_address(address: Addr) -> Addr {
+    if address.as_str().to_lowercase().starts_with("0x") {
+        Addr::unchecked(address.as_str().to_lowercase())
+    } else {
+        address
+    }
+}
+
+
 /// Execute permit message
 ///
-/// Verify that the permit is valid. Then, execute the query message:
-/// - StoreNewFile: Store a new file in the Smart contract.
-/// - ManageFileRights: Update / Revoke rights for a given file.
+/// Verify that the permit is valid and has the permission required by the
+/// action. Then, execute the action for the account of the permit.
 fn execute_permit_message(
-    deps: DepsMut, 
-    permit: Permit, 
+    mut deps: DepsMut, 
+    env: Env,
+    permit: Permit<DocumentPermission>, 
+    signature_scheme: SignatureScheme,
     query: ExecuteMsgAction
 ) -> Result<Response, ContractError> {
 
     // Verify the account
     let contract_address = CONFIG.load(deps.storage)?.contract_address;
-    let account = _verify_permit(deps.as_ref(), permit, contract_address)?;
+    let ethereum_address = _ethereum_address(&permit.signature.pub_key.value)?;
+    let account = _verify_permit(deps.as_ref(), permit.clone(), signature_scheme, contract_address, &env.block.chain_id)?;
+
+    // Verify the permit allows the action
+    if let Some(permission) = _action_permission(&query) {
+        _check_permit_permission(&permit, permission)?;
+    }
+
+    // Attach the rights given to the Ethereum address of the user to his account,
+    // and remember the link for the queries with a viewing key
+    resolve_ethereum_grants(deps.branch(), &ethereum_address, &account)?;
+    ETHEREUM_ADDRESSES.insert(deps.storage, &account, &ethereum_address)?;
+
+    execute_action(deps, env, account, Some(ethereum_address), query)
+}
+
+
+/// Execute an action for an authenticated account.
+///
+/// The account is either proved by a permit, or is the sender of a direct
+/// message on Secret Network. The Ethereum address of the user is only known
+/// with a permit. The actions:
+/// - StoreNewFile: Store a new file in the Smart contract.
+/// - ManageFileRights: Update / Revoke rights for a given file.
+/// - ProposeOwnershipTransfer: Propose a new owner for a given file.
+/// - AcceptOwnership: Accept the ownership of a given file.
+/// - CancelOwnershipTransfer: Cancel a pending ownership transfer.
+/// - RequestAccess: Ask the owner of a file for a viewing access.
+/// - ApproveRequest / DenyRequest: Answer an access request.
+/// - RegisterClaimCode / RevokeClaimCode: Manage the invitations to a given file.
+/// - ClaimAccess: Get a viewing access from an invitation.
+/// - CreateGroup: Create a new group of users.
+/// - UpdateGroupMembers: Add / Remove members of a group.
+/// - CreateFolder: Create a new folder, optionally inside another folder.
+/// - ManageFolderRights: Add / Remove viewing rights on a folder.
+/// - MoveFile / MoveFolder: Move a file or a folder to another folder.
+/// - RevokePermit: Revoke a permit of the user.
+/// - AddFileKeys: Store the key of a given file wrapped for some users.
+/// - DeleteFile: Delete a given file and all its references.
+/// - UpdateFileInfo: Update the name, type... of a given file.
+/// - UpdateFile: Add a new version of a given file.
+/// - RekeyFile: Replace the payload of a given file after a revocation.
+pub fn execute_action(
+    mut deps: DepsMut, 
+    env: Env,
+    account: Addr,
+    ethereum_address: Option<Addr>,
+    action: ExecuteMsgAction
+) -> Result<Response, ContractError> {
 
     // Execute the message
-    match query {
-        ExecuteMsgAction::StoreNewFile { payload } => {
-            store_new_file(deps, account, payload)?;
+    match action {
+        ExecuteMsgAction::StoreNewFile { payload, info } => {
+            let info = info.unwrap_or_default();
+            _validate_file_info(&info)?;
+            store_new_file(deps, &env.block, account, payload, info)?;
         },
         ExecuteMsgAction::ManageFileRights { 
             file_id, 
             add_viewing, 
             delete_viewing, 
-            change_owner 
+            change_owner,
+            roles,
+            add_groups,
+            delete_groups,
+            rekey
         } => {
-            
-            // Decode the file key 
-            let extracted_key = hex::decode(file_id)?;
-            let extracted_key: [u8; 32] = extracted_key.try_into().unwrap();
-        
-            // Get the file metadata
-            let file_metadata_store = ReadonlyPrefixedStorage::new(deps.storage, PREFIX_FILES_METADATA);
-            let loaded_metadata: Option<FileMetadata> = may_load(&file_metadata_store, &extracted_key)?;
 
-            // Be sure that the file exists
-            let metadata = match loaded_metadata {
-                Some(metadata) => metadata,
-                _ => return Err(ContractError::InvalidFileID)
-            };
+            let (extracted_key, metadata) = _load_file_metadata(deps.storage, file_id, &account)?;
+
+            let add_viewing: Vec<Addr> = add_viewing.into_iter().map(_normalize_address).collect();
+            let delete_viewing: Vec<Addr> = delete_viewing.into_iter().map(_normalize_address).collect();
+            let roles: Vec<RoleAssignment> = roles.into_iter().map(|assignment| RoleAssignment {
+                address: _normalize_address(assignment.address),
+                ..assignment
+            }).collect();
+
+            // The owner can only be changed with a transfer accepted by the new owner
+            if change_owner.is_some_and(|new_owner| new_owner != metadata.owner) {
+                return Err(ContractError::OwnershipTransferRequired)
+            }
 
-            // Check that only the owner can update the permissions
-            if metadata.owner == account {
-                update_file_access(
-                    deps,
-                    extracted_key, 
-                    add_viewing, 
-                    delete_viewing, 
-                    change_owner
-                )?;
-                
-            } else {
+            // Check that only the owner or a manager can update the permissions
+            let role = get_file_role(deps.storage, &env.block, extracted_key, &account);
+            if role < Some(FileRole::Manager) {
                 return Err(ContractError::UnauthorizedAccess)
             }
 
-        }
-    };
+            // A manager can only manage the viewers and the editors of the file
+            if role != Some(FileRole::Owner) {
+                let targets_manager = roles.iter().map(|assignment| &assignment.address)
+                    .chain(delete_viewing.iter())
+                    .any(|user| get_file_role(deps.storage, &env.block, extracted_key, user) >= Some(FileRole::Manager));
 
-    Ok(Response::default())
-}
+                if roles.iter().any(|assignment| assignment.role >= FileRole::Manager)
+                    || targets_manager 
+                {
+                    return Err(ContractError::UnauthorizedAccess)
+                }
+            }
 
+            update_file_groups(deps.branch(), &account, extracted_key, add_groups, delete_groups)?;
+
+            update_file_access(
+                deps.branch(),
+                &env,
+                extracted_key, 
+                add_viewing, 
+                delete_viewing, 
+                roles
+            )?;
+
+            // The removed users may still know the key, the owner must replace it
+            if rekey {
+                let mut file_metadata_store = PrefixedStorage::new(deps.storage, PREFIX_FILES_METADATA);
+                let mut file_metadata: FileMetadata = load(&file_metadata_store, &extracted_key)?;
+                file_metadata.pending_rekey = true;
+                save(&mut file_metadata_store, &extracted_key, &file_metadata)?;
+            }
 
-/// Decrypt a cyphertext using a given public key and the contract private key.
-///
-/// Create a shared secret by using the user public key and the contract private key.
-/// Then, used this shared secet to decrypt the cyphertext.
-/// 
-/// Note: for the ExecutePermitMsg, we cannot use Bincode2 as encoder as we are using 
-/// enum values, which is not manage by this library.
-fn _decrypt_with_user_public_key(
-    deps: &DepsMut,
-    payload: Vec<u8>,
-    user_public_key: Vec<u8>,
-) -> Result<ExecutePermitMsg, ContractError> {
-    // Read the private key from the storage
-    let contract_keys = CONTRACT_KEYS.load(deps.storage)?;
+        },
+        ExecuteMsgAction::ProposeOwnershipTransfer { file_id, new_owner } => {
+            let (extracted_key, metadata) = _load_file_metadata(deps.storage, file_id, &account)?;
+
+            if metadata.owner != account {
+                return Err(ContractError::UnauthorizedAccess)
+            }
+
+            let new_owner = _normalize_address(new_owner);
+            if new_owner == metadata.owner {
+                return Err(ContractError::CustomError { val: String::from("The new owner is already the owner of the file") });
+            }
+
+            PENDING_OWNERS.insert(deps.storage, &extracted_key, &new_owner)?;
+        },
+        ExecuteMsgAction::AcceptOwnership { file_id } => {
+            let (extracted_key, _metadata) = _load_file_metadata(deps.storage, file_id, &account)?;
+
+            // The new owner proves his identity with his own permit
+            let pending_owner = PENDING_OWNERS.get(deps.storage, &extracted_key);
+            if pending_owner.is_none() 
+                || (pending_owner != Some(account.clone()) && pending_owner != ethereum_address) 
+            {
+                return Err(ContractError::NoPendingOwnershipTransfer)
+            }
+
+            transfer_file_ownership(deps, extracted_key, account)?;
+        },
+        ExecuteMsgAction::CancelOwnershipTransfer { file_id } => {
+            let (extracted_key, metadata) = _load_file_metadata(deps.storage, file_id, &account)?;
+
+            if metadata.owner != account {
+                return Err(ContractError::UnauthorizedAccess)
+            }
+
+            if PENDING_OWNERS.get(deps.storage, &extracted_key).is_none() {
+                return Err(ContractError::NoPendingOwnershipTransfer)
+            }
+
+            PENDING_OWNERS.remove(deps.storage, &extracted_key)?;
+        },
+        ExecuteMsgAction::RequestAccess { file_id, note } => {
+            if note.len() > MAX_ACCESS_REQUEST_NOTE_LENGTH {
+                return Err(ContractError::CustomError { 
+                    val: format!("The note cannot exceed {} bytes", MAX_ACCESS_REQUEST_NOTE_LENGTH) 
+                });
+            }
+
+            // The same error whether the file exists or not
+            let extracted_key = match _load_file_metadata(deps.storage, file_id, &account) {
+                Ok((extracted_key, _metadata)) => extracted_key,
+                Err(_) => return Err(ContractError::InvalidAccessRequest)
+            };
+
+            if get_file_role(deps.storage, &env.block, extracted_key, &account).is_some() {
+                return Err(ContractError::InvalidAccessRequest)
+            }
+
+            // A denied request cannot be renewed, a new request replaces the previous one otherwise
+            let previous_request = ACCESS_REQUESTS.add_suffix(&extracted_key).get(deps.storage, &account);
+            if previous_request.is_some_and(|request| request.status == AccessRequestStatus::Denied) {
+                return Err(ContractError::InvalidAccessRequest)
+            }
+
+            let access_request = AccessRequest {
+                note,
+                status: AccessRequestStatus::Pending,
+                requested_at: env.block.time,
+            };
+            ACCESS_REQUESTS.add_suffix(&extracted_key).insert(deps.storage, &account, &access_request)?;
+            PENDING_ACCESS_REQUESTS.add_suffix(&extracted_key).insert(deps.storage, &account, &true)?;
+        },
+        ExecuteMsgAction::ApproveRequest { file_id, requester } => {
+            let (extracted_key, _metadata) = _load_file_metadata(deps.storage, file_id, &account)?;
+
+            if get_file_role(deps.storage, &env.block, extracted_key, &account) < Some(FileRole::Manager) {
+                return Err(ContractError::UnauthorizedAccess)
+            }
+
+            answer_access_request(deps.storage, extracted_key, &requester, AccessRequestStatus::Approved)?;
+
+            // Give a viewing access to the requester
+            update_file_access(
+                deps,
+                &env,
+                extracted_key,
+                Vec::from([requester]),
+                Vec::new(),
+                Vec::new()
+            )?;
+        },
+        ExecuteMsgAction::DenyRequest { file_id, requester } => {
+            let (extracted_key, _metadata) = _load_file_metadata(deps.storage, file_id, &account)?;
+
+            if get_file_role(deps.storage, &env.block, extracted_key, &account) < Some(FileRole::Manager) {
+                return Err(ContractError::UnauthorizedAccess)
+            }
+
+            answer_access_request(deps.storage, extracted_key, &requester, AccessRequestStatus::Denied)?;
+        },
+        ExecuteMsgAction::RegisterClaimCode { 
+            file_id, 
+            claim_hash, 
+            max_claims, 
+            expires_at 
+        } => {
+            let (extracted_key, _metadata) = _load_file_metadata(deps.storage, file_id, &account)?;
+
+            if get_file_role(deps.storage, &env.block, extracted_key, &account) < Some(FileRole::Manager) {
+                return Err(ContractError::UnauthorizedAccess)
+            }
+
+            let claim_key = hex::decode(claim_hash)?;
+            let claim_key: [u8; 32] = claim_key.try_into()
+                .map_err(|_| ContractError::InvalidClaimCode)?;
+
+            if CLAIM_CODES.get(deps.storage, &claim_key).is_some() {
+                return Err(ContractError::CustomError { val: String::from("The claim code is already registered") });
+            }
+
+            let claim_code = ClaimCode {
+                file_key: extracted_key,
+                max_claims,
+                claims: 0,
+                expires_at,
+            };
+            CLAIM_CODES.insert(deps.storage, &claim_key, &claim_code)?;
+            FILE_CLAIM_CODES.add_suffix(&extracted_key).insert(deps.storage, &claim_key, &true)?;
+        },
+        ExecuteMsgAction::RevokeClaimCode { claim_hash } => {
+            let claim_key = hex::decode(claim_hash)?;
+            let claim_key: [u8; 32] = claim_key.try_into()
+                .map_err(|_| ContractError::InvalidClaimCode)?;
+
+            let claim_code = match CLAIM_CODES.get(deps.storage, &claim_key) {
+                Some(claim_code) => claim_code,
+                None => return Err(ContractError::InvalidClaimCode)
+            };
+
+            if get_file_role(deps.storage, &env.block, claim_code.file_key, &account) < Some(FileRole::Manager) {
+                return Err(ContractError::UnauthorizedAccess)
+            }
+
+            CLAIM_CODES.remove(deps.storage, &claim_key)?;
+            FILE_CLAIM_CODES.add_suffix(&claim_code.file_key).remove(deps.storage, &claim_key)?;
+        },
+        ExecuteMsgAction::ClaimAccess { preimage } => {
+            claim_file_access(deps, &env, account, preimage)?;
+        },
+        ExecuteMsgAction::CreateGroup { name, members } => {
+            let members: Vec<Addr> = members.into_iter().map(_normalize_address).collect();
+            create_group(deps, &env.block, account, name, members)?;
+        },
+        ExecuteMsgAction::UpdateGroupMembers { 
+            group_id, 
+            add_members, 
+            remove_members 
+        } => {
+
+            // Decode the group key
+            let extracted_key = hex::decode(group_id)?;
+            let extracted_key: [u8; 32] = extracted_key.try_into()
+                .map_err(|_| ContractError::InvalidGroupID)?;
+
+            let add_members: Vec<Addr> = add_members.into_iter().map(_normalize_address).collect();
+            let remove_members: Vec<Addr> = remove_members.into_iter().map(_normalize_address).collect();
+
+            update_group_members(deps, account, extracted_key, add_members, remove_members)?;
+        },
+        ExecuteMsgAction::CreateFolder { name, parent } => {
+
+            if name.len() > MAX_FOLDER_NAME_LENGTH {
+                return Err(ContractError::CustomError { val: format!("The name of a folder cannot exceed {} bytes", MAX_FOLDER_NAME_LENGTH) })
+            }
+
+            // Only the owner of the parent folder can create a sub-folder
+            let parent_key = match parent {
+                Some(parent) => {
+                    let (parent_key, parent_folder) = _load_folder(deps.storage, parent)?;
+                    if parent_folder.owner != account {
+                        return Err(ContractError::UnauthorizedFolderAccess)
+                    }
+                    _check_folder_nesting(deps.storage, parent_key, &parent_folder, 1)?;
+                    Some(parent_key)
+                },
+                None => None,
+            };
+
+            create_folder(deps, &env.block, account, name, parent_key)?;
+        },
+        ExecuteMsgAction::ManageFolderRights { 
+            folder_id, 
+            add_viewing, 
+            delete_viewing 
+        } => {
+
+            let (extracted_key, folder) = _load_folder(deps.storage, folder_id)?;
+
+            // Only the owner can manage the folder
+            if folder.owner != account {
+                return Err(ContractError::UnauthorizedFolderAccess)
+            }
+
+            let add_viewing: Vec<Addr> = add_viewing.into_iter().map(_normalize_address).collect();
+            let delete_viewing: Vec<Addr> = delete_viewing.into_iter().map(_normalize_address).collect();
+
+            update_folder_access(deps, extracted_key, folder, add_viewing, delete_viewing)?;
+        },
+        ExecuteMsgAction::MoveFile { file_id, folder_id } => {
+
+            let (extracted_key, metadata) = _load_file_metadata(deps.storage, file_id, &account)?;
+
+            // Only the owner can move the file, the folder gives access to the file
+            if metadata.owner != account {
+                return Err(ContractError::UnauthorizedAccess)
+            }
+
+            // Only the owner of the destination folder can add files in it
+            let folder_key = match folder_id {
+                Some(folder_id) => {
+                    let (folder_key, folder) = _load_folder(deps.storage, folder_id)?;
+                    if folder.owner != account {
+                        return Err(ContractError::UnauthorizedFolderAccess)
+                    }
+                    if folder.files.len() >= MAX_FOLDER_FILES && !folder.files.contains(&extracted_key) {
+                        return Err(ContractError::CustomError { val: format!("A folder cannot contain more than {} files", MAX_FOLDER_FILES) })
+                    }
+                    Some(folder_key)
+                },
+                None => None,
+            };
+
+            move_file(deps, extracted_key, metadata, folder_key)?;
+        },
+        ExecuteMsgAction::MoveFolder { folder_id, parent } => {
+
+            let (extracted_key, folder) = _load_folder(deps.storage, folder_id)?;
+
+            if folder.owner != account {
+                return Err(ContractError::UnauthorizedFolderAccess)
+            }
+
+            let parent_key = match parent {
+                Some(parent) => {
+                    let (parent_key, parent_folder) = _load_folder(deps.storage, parent)?;
+                    if parent_folder.owner != account {
+                        return Err(ContractError::UnauthorizedFolderAccess)
+                    }
+
+                    // A folder cannot be moved inside itself
+                    if _folder_ancestors(deps.storage, parent_key).iter().any(|(key, _)| *key == extracted_key) {
+                        return Err(ContractError::CustomError { val: String::from("A folder cannot be moved inside itself") })
+                    }
+                    if !parent_folder.folders.contains(&extracted_key) {
+                        _check_folder_nesting(deps.storage, parent_key, &parent_folder, _folder_height(deps.storage, &folder))?;
+                    }
+                    Some(parent_key)
+                },
+                None => None,
+            };
+
+            move_folder(deps, extracted_key, folder, parent_key)?;
+        },
+        ExecuteMsgAction::AddFileKeys { file_id, keys } => {
+            let (extracted_key, _metadata) = _load_file_metadata(deps.storage, file_id, &account)?;
+
+            if get_file_role(deps.storage, &env.block, extracted_key, &account) < Some(FileRole::Manager) {
+                return Err(ContractError::UnauthorizedAccess)
+            }
+
+            add_file_keys(deps.storage, &env.block, extracted_key, keys)?;
+        },
+        ExecuteMsgAction::DeleteFile { file_id, tombstone } => {
+            let (extracted_key, metadata) = _load_file_metadata(deps.storage, file_id, &account)?;
+
+            if metadata.owner != account {
+                return Err(ContractError::UnauthorizedAccess)
+            }
+
+            // Only the users who could see the file are told it was deleted
+            let former_viewers = _file_audience(deps.storage, &metadata);
+
+            delete_file(deps.storage, extracted_key, metadata)?;
+
+            if tombstone {
+                DELETED_FILES.insert(deps.storage, &extracted_key, &env.block.time)?;
+                let deleted_file_viewers = DELETED_FILE_VIEWERS.add_suffix(&extracted_key);
+                for viewer in former_viewers {
+                    deleted_file_viewers.insert(deps.storage, &viewer, &true)?;
+                }
+            }
+        },
+        ExecuteMsgAction::UpdateFileInfo { file_id, info } => {
+            let (extracted_key, mut metadata) = _load_file_metadata(deps.storage, file_id, &account)?;
+
+            if metadata.owner != account {
+                return Err(ContractError::UnauthorizedAccess)
+            }
+
+            _validate_file_info(&info)?;
+
+            metadata.info = info;
+            metadata.updated_at = Some(env.block.time);
+            let mut file_metadata_store = PrefixedStorage::new(deps.storage, PREFIX_FILES_METADATA);
+            save(&mut file_metadata_store, &extracted_key, &metadata)?;
+        },
+        ExecuteMsgAction::UpdateFile { file_id, payload } => {
+            let (extracted_key, metadata) = _load_file_metadata(deps.storage, file_id, &account)?;
+
+            if get_file_role(deps.storage, &env.block, extracted_key, &account) < Some(FileRole::Editor) {
+                return Err(ContractError::UnauthorizedAccess)
+            }
+
+            // The owner must first replace the payload with a new key
+            if metadata.pending_rekey {
+                return Err(ContractError::FilePendingRekey)
+            }
+
+            update_file(deps.storage, &env.block, extracted_key, payload, &account)?;
+        },
+        ExecuteMsgAction::RekeyFile { file_id, payload, keys } => {
+            let (extracted_key, metadata) = _load_file_metadata(deps.storage, file_id, &account)?;
+
+            if metadata.owner != account {
+                return Err(ContractError::UnauthorizedAccess)
+            }
+
+            rekey_file(deps.storage, &env.block, extracted_key, &account, payload, keys)?;
+        },
+        ExecuteMsgAction::RevokePermit { permit_name } => {
+            revoke_permit(deps, &account, permit_name)?;
+        }
+    };
+
+    Ok(Response::default())
+}
+
+
+/// Decode a file id and load the metadata of the file.
+///
+/// A deleted file is only reported as such to the users who could see it, the
+/// others get the same error as for a file that never existed.
+fn _load_file_metadata(
+    storage: &dyn Storage, 
+    file_id: String,
+    account: &Addr
+) -> Result<([u8; 32], FileMetadata), ContractError> {
+
+    // Decode the file key 
+    let extracted_key = hex::decode(file_id)?;
+    let extracted_key: [u8; 32] = extracted_key.try_into()
+        .map_err(|_| ContractError::InvalidFileID)?;
+
+    // Get the file metadata
+    let file_metadata_store = ReadonlyPrefixedStorage::new(storage, PREFIX_FILES_METADATA);
+    let loaded_metadata: Option<FileMetadata> = may_load(&file_metadata_store, &extracted_key)?;
+
+    // Be sure that the file exists
+    match loaded_metadata {
+        Some(metadata) => Ok((extracted_key, metadata)),
+        _ if DELETED_FILE_VIEWERS.add_suffix(&extracted_key).contains(storage, account) => Err(ContractError::FileDeleted),
+        _ => Err(ContractError::InvalidFileID)
+    }
+}
+
+
+/// Decode a folder id and load the folder.
+fn _load_folder(
+    storage: &dyn Storage, 
+    folder_id: String
+) -> Result<([u8; 32], Folder), ContractError> {
+
+    // Decode the folder key 
+    let extracted_key = hex::decode(folder_id)?;
+    let extracted_key: [u8; 32] = extracted_key.try_into()
+        .map_err(|_| ContractError::InvalidFolderID)?;
+
+    match FOLDERS.get(storage, &extracted_key) {
+        Some(folder) => Ok((extracted_key, folder)),
+        None => Err(ContractError::InvalidFolderID)
+    }
+}
+
+
+/// Create a shared secret from the user public key and the contract private key.
+fn _shared_secret(
+    contract_keys: &ContractKeys,
+    user_public_key: Vec<u8>,
+) -> Result<[u8; 32], ContractError> {
+    // Read the private key of the version used by the user
     let contract_private_key = SecretKey::from_slice(contract_keys.private_key.as_slice()).unwrap();
 
     // Conver the user public key
@@ -336,9 +1387,49 @@ fn _decrypt_with_user_public_key(
             ContractError::InvalidPublicKey { val: e.to_string() }
         })?;
 
-    // Create a shared secret from the user public key and the conrtact private key
     let shared_secret = SharedSecret::new(&user_public_key, &contract_private_key);
-    let key = shared_secret.secret_bytes();
+    Ok(shared_secret.secret_bytes())
+}
+
+
+/// Encrypt a query response for the user, with a shared secret created from the
+/// ephemeral public key given by the user and the current contract private key.
+///
+/// Only the user can read the response, which is not visible by the nodes
+/// and services between his wallet and the contract.
+fn _encrypt_for_user_public_key(
+    deps: Deps,
+    response: Binary,
+    user_public_key: Vec<u8>,
+) -> Result<Binary, ContractError> {
+    let contract_keys = CONTRACT_KEYS.load(deps.storage)?;
+    let key = _shared_secret(&contract_keys, user_public_key)?;
+
+    let ad_data: &[&[u8]] = &[];
+    let ad = Some(ad_data);
+
+    let encrypted_data = aes_siv_encrypt(response.as_slice(), ad, &key)?;
+
+    Ok(to_binary(&EncryptedQueryResponse {
+        payload: encrypted_data,
+        key_version: contract_keys.version,
+    })?)
+}
+
+
+/// Decrypt a cyphertext using a given public key and the contract private key.
+///
+/// Create a shared secret by using the user public key and the contract private key.
+/// Then, used this shared secet to decrypt the cyphertext.
+/// 
+/// Note: for the ExecutePermitMsg, we cannot use Bincode2 as encoder as we are using 
+/// enum values, which is not manage by this library.
+fn _decrypt_with_user_public_key(
+    contract_keys: &ContractKeys,
+    payload: Vec<u8>,
+    user_public_key: Vec<u8>,
+) -> Result<ExecutePermitMsg, ContractError> {
+    let key = _shared_secret(contract_keys, user_public_key)?;
 
     let ad_data: &[&[u8]] = &[];
     let ad = Some(ad_data);
@@ -359,6 +1450,21 @@ fn _decrypt_with_user_public_key(
 }
 
 
+/// Encrypt AES message.
+pub fn aes_siv_encrypt(
+    plaintext: &[u8],
+    ad: Option<&[&[u8]]>,
+    key: &[u8],
+) -> Result<Vec<u8>, ContractError> {
+    let ad = ad.unwrap_or(&[&[]]);
+
+    let mut cipher = Aes128Siv::new(GenericArray::clone_from_slice(key));
+    cipher.encrypt(ad, plaintext).map_err(|_e| {
+        ContractError::EncryptionError
+    })
+}
+
+
 /// Decrypt AES message.
 pub fn aes_siv_decrypt(
     plaintext: &[u8],
@@ -377,12 +1483,15 @@ pub fn aes_siv_decrypt(
 /// Create a key from a given file data.
 ///
 /// When storing new data, we need to have a unique key for the given data.
-/// We decide to create a hash using the struct element to have a unique
-/// key, allowing us to store the data.
+/// The counter makes the key unique, and the secret entropy makes it 
+/// unpredictable: nobody can enumerate the keys nor count them.
 ///
-pub fn generate_unique_id(index: &u128) -> [u8; 32] {
+/// The keys created before were only the hash of the counter. They are still
+/// valid, as the keys are stored and never computed again.
+pub fn generate_unique_id(index: &u128, entropy: &[u8]) -> [u8; 32] {
     // Hash the data
     let mut hasher = Sha256::new();
+    hasher.update(entropy);
     hasher.update(index.to_le_bytes());
     
     // Retrieve the hash
@@ -392,6 +1501,24 @@ pub fn generate_unique_id(index: &u128) -> [u8; 32] {
 }
 
 
+/// Increment the counter of the contract and create a new unique key.
+///
+/// The secret salt of the contract and the randomness of the block are used as
+/// entropy. Without the randomness, the salt alone keeps the ids unpredictable.
+pub fn next_unique_id(storage: &mut dyn Storage, block: &BlockInfo) -> StdResult<[u8; 32]> {
+    let mut config = CONFIG.load(storage)?;
+    config.index = config.index + 1;
+    CONFIG.save(storage, &config)?;
+
+    let mut entropy = ID_SALT.load(storage)?.to_vec();
+    if let Some(random) = &block.random {
+        entropy.extend_from_slice(random.as_slice());
+    }
+
+    Ok(generate_unique_id(&config.index, &entropy))
+}
+
+
 /// Add a key to a user
 ///
 /// Get the user information if it exists. Else, create a new user information.
@@ -419,38 +1546,51 @@ pub fn add_file_key_to_user(deps: DepsMut, owner: Addr, file_key: [u8; 32]) -> S
 
 
 /// Store a new file in the smartcontract storage
-pub fn store_new_file(deps: DepsMut, owner: Addr, payload: String) -> StdResult<String> {
+pub fn store_new_file(
+    deps: DepsMut, 
+    block: &BlockInfo, 
+    owner: Addr, 
+    payload: String, 
+    info: FileInfo
+) -> StdResult<String> {
     
     // Get a unique id
-    let mut config = CONFIG.load(deps.storage)?;
-    config.index = config.index + 1;
-    CONFIG.save(deps.storage, &config)?;
-
-    let id = config.index;
+    let key: [u8; 32] = next_unique_id(deps.storage, block)?;
     
     // Get the storage for files
     let mut file_storage = PrefixedStorage::new(deps.storage, PREFIX_FILES);
 
     // Create the file content
     let file_state = FileState {
-        payload: payload,
+        payload: payload.clone(),
+        version: 0,
     };
 
-    let key: [u8; 32] = generate_unique_id(&id);
-
-    // Save the file
+    // Save the file and its first version
     save(&mut file_storage, &key, &file_state)?;
+    add_file_version(deps.storage, block, key, 0, payload, &owner)?;
 
     // Save associated metadata
     let mut file_metadata_storage = PrefixedStorage::new(deps.storage, PREFIX_FILES_METADATA);
     let file_metadata = FileMetadata {
         owner: owner.clone(),
-        viewers: Vec::from([owner.clone()])
+        viewers: Vec::from([owner.clone()]),
+        groups: Vec::new(),
+        folder: None,
+        pending_rekey: false,
+        rotated_at: None,
+        info,
+        created_at: Some(block.time),
+        updated_at: Some(block.time),
     };
     save(&mut file_metadata_storage, &key, &file_metadata)?;
 
-    // Add the viewing right for the user
-    FILE_PERMISSIONS.insert(deps.storage, &(key, owner.clone()), &true)?;
+    // Add the owner role for the user
+    let permission = FilePermission {
+        role: FileRole::Owner,
+        expires_at: None,
+    };
+    FILE_PERMISSIONS.insert(deps.storage, &(key, owner.clone()), &permission)?;
 
     // Add the key to the user
     add_file_key_to_user(deps, owner.clone(), key)?;
@@ -460,925 +1600,4966 @@ pub fn store_new_file(deps: DepsMut, owner: Addr, payload: String) -> StdResult<
 }
 
 
-/// Update file permissions
-pub fn update_file_access(
-    deps: DepsMut,
+/// Replace the payload of a file, keeping the previous versions.
+pub fn update_file(
+    storage: &mut dyn Storage, 
+    block: &BlockInfo, 
     file_key: [u8; 32], 
-    add_viewing: Vec<Addr>, 
-    delete_viewing: Vec<Addr>, 
-    change_owner: Addr
-) -> Result<(), ContractError> {
+    payload: String, 
+    author: &Addr
+) -> StdResult<u32> {
 
-    // Load the file metadata
-    let file_metadata_store = PrefixedStorage::new(deps.storage, PREFIX_FILES_METADATA);
+    let mut file_metadata_store = PrefixedStorage::new(storage, PREFIX_FILES_METADATA);
     let mut file_metadata: FileMetadata = load(&file_metadata_store, &file_key)?;
-    
-    // Add all viewing access
-    for user_add in &add_viewing {
-
-        // Check if the user already has access
-        let already_added = FILE_PERMISSIONS.get(deps.storage, &(file_key, user_add.clone()));
-        if already_added.is_none() || already_added.is_some_and(|x| !x) {
-            // Add permission
-            FILE_PERMISSIONS.insert(deps.storage, &(file_key, user_add.clone()), &true)?;
-
-            // Add the file in the list of user view
-            let users_store = ReadonlyPrefixedStorage::new(deps.storage, PREFIX_USERS);
-            let loaded_payload: StdResult<Option<UserInfo>> = may_load(&users_store, user_add.as_bytes());
-
-            // The user can already exists or not.
-            let mut user_info = match loaded_payload {
-                Ok(Some(user_info)) => user_info,
-                _ => UserInfo {
-                    files: Vec::new()
-                }
-            };
-
-            user_info.files.push(file_key);
-
-            // Save the updated information
-            let mut users_store = PrefixedStorage::new(deps.storage, PREFIX_USERS);
-            save(&mut users_store, user_add.as_bytes(), &user_info)?;
+    file_metadata.updated_at = Some(block.time);
+    save(&mut file_metadata_store, &file_key, &file_metadata)?;
 
-            // Update the file metadata
-            file_metadata.viewers.push(user_add.clone());
+    // Keep the payload of a file stored before the versioning as its first version
+    if FILE_VERSIONS.add_suffix(&file_key).is_empty(storage)? {
+        for (version, file_version) in _load_file_versions(storage, file_key, &file_metadata)? {
+            FILE_VERSIONS.add_suffix(&file_key).insert(storage, &version, &file_version)?;
         }
+    }
 
-    };
-
-    // Delete viewing access
-    for user_delete in &delete_viewing {
+    let mut file_storage = PrefixedStorage::new(storage, PREFIX_FILES);
+    let mut file_state: FileState = load(&file_storage, &file_key)?;
 
-        // Check if the user has a viewing right
-        let already_added = FILE_PERMISSIONS.get(deps.storage, &(file_key, user_delete.clone()));
-        if already_added.is_some() {
+    file_state.version = file_state.version + 1;
+    file_state.payload = payload.clone();
+    save(&mut file_storage, &file_key, &file_state)?;
 
-            if user_delete == &change_owner {
-                return Err( ContractError::CustomError { val: String::from("Cannot remove viewing right from the new owner") });
-            }
+    add_file_version(storage, block, file_key, file_state.version, payload, author)?;
 
-            // Remove permission
-            FILE_PERMISSIONS.remove(deps.storage, &(file_key, user_delete.clone()))?;
+    Ok(file_state.version)
+}
 
-            // Remove the file from the user list
-            let users_store = ReadonlyPrefixedStorage::new(deps.storage, PREFIX_USERS);
-            let loaded_payload: StdResult<Option<UserInfo>> = load(&users_store, user_delete.as_bytes());
 
-            let mut user_info = match loaded_payload {
-                Ok(Some(user_info)) => user_info,
-                _ => UserInfo {
-                    files: Vec::new()
-                }
-            };
+/// Load the versions of a file, from the first one.
+///
+/// A file stored before the versioning has no version: its payload is returned 
+/// as its only version, until the first update records it.
+fn _load_file_versions(
+    storage: &dyn Storage, 
+    file_key: [u8; 32], 
+    file_metadata: &FileMetadata
+) -> StdResult<Vec<(u32, FileVersion)>> {
+
+    let mut versions = FILE_VERSIONS.add_suffix(&file_key).iter(storage)?
+        .collect::<StdResult<Vec<(u32, FileVersion)>>>()?;
+
+    if versions.is_empty() {
+        let file_storage = ReadonlyPrefixedStorage::new(storage, PREFIX_FILES);
+        let file_state: FileState = load(&file_storage, &file_key)?;
+        versions.push((file_state.version, FileVersion {
+            payload: file_state.payload,
+            author: file_metadata.owner.clone(),
+            created_at: file_metadata.created_at,
+        }));
+    }
 
-            // Get the index of the file and remove it
-            let index = user_info.files.iter().position(|x| *x == file_key).unwrap();
-            user_info.files.remove(index);
+    versions.sort_by_key(|(version, _)| *version);
 
-            // Update user information
-            let mut users_store = PrefixedStorage::new(deps.storage, PREFIX_USERS);
-            save(&mut users_store, user_delete.as_bytes(), &user_info)?;
+    Ok(versions)
+}
 
-            // Remove the user from the list
-            let index = file_metadata.viewers.iter().position(|x| x == user_delete).unwrap();
-            file_metadata.viewers.remove(index);
-        }
 
-    };
+/// Delete a file with its versions, and remove it from every index.
+///
+/// All the users lose their access, including the ones given by a group or a folder.
+/// The claim codes of the file are not listed by file, they can no longer be used 
+/// as the file does not exist anymore.
+fn delete_file(
+    storage: &mut dyn Storage,
+    file_key: [u8; 32],
+    mut file_metadata: FileMetadata
+) -> StdResult<()> {
+
+    // Remove the permissions, the wrapped keys and the file from the user lists
+    for viewer in file_metadata.viewers.clone() {
+        remove_file_role(storage, file_key, &viewer, &mut file_metadata)?;
+    }
 
-    // Update the owner
-    if file_metadata.owner != change_owner {
-        file_metadata.owner = change_owner;
+    for group_key in &file_metadata.groups {
+        if let Some(mut group) = GROUPS.get(storage, group_key) {
+            group.files.retain(|x| *x != file_key);
+            GROUPS.insert(storage, group_key, &group)?;
+        }
+    }
 
-        // Be sure that the new owner have access to view the file
-        let already_added = FILE_PERMISSIONS.get(deps.storage, &(file_key, file_metadata.owner.clone()));
-        if already_added.is_none() || already_added.is_some_and(|x| !x) {
+    if let Some(folder_key) = file_metadata.folder {
+        if let Some(mut folder) = FOLDERS.get(storage, &folder_key) {
+            folder.files.retain(|x| *x != file_key);
+            FOLDERS.insert(storage, &folder_key, &folder)?;
+        }
+    }
 
-            FILE_PERMISSIONS.insert(deps.storage, &(file_key, file_metadata.owner.clone()), &true)?;
+    // Remaining wrapped keys, versions and access requests of the file
+    let file_keys = FILE_KEYS.add_suffix(&file_key);
+    let users = file_keys.iter_keys(storage)?.collect::<StdResult<Vec<Addr>>>()?;
+    for user in users {
+        file_keys.remove(storage, &user)?;
+    }
 
-            // Add the file in the list of user view
-            let users_store = ReadonlyPrefixedStorage::new(deps.storage, PREFIX_USERS);
-            let loaded_payload: StdResult<Option<UserInfo>> = may_load(&users_store, file_metadata.owner.as_bytes());
+    let file_versions = FILE_VERSIONS.add_suffix(&file_key);
+    let versions = file_versions.iter_keys(storage)?.collect::<StdResult<Vec<u32>>>()?;
+    for version in versions {
+        file_versions.remove(storage, &version)?;
+    }
 
-            let mut user_info = match loaded_payload {
-                Ok(Some(user_info)) => user_info,
-                _ => UserInfo {
-                    files: Vec::new()
-                }
-            };
+    let file_requests = ACCESS_REQUESTS.add_suffix(&file_key);
+    let requesters = file_requests.iter_keys(storage)?.collect::<StdResult<Vec<Addr>>>()?;
+    for requester in requesters {
+        file_requests.remove(storage, &requester)?;
+    }
 
-            user_info.files.push(file_key);
+    let pending_requests = PENDING_ACCESS_REQUESTS.add_suffix(&file_key);
+    let requesters = pending_requests.iter_keys(storage)?.collect::<StdResult<Vec<Addr>>>()?;
+    for requester in requesters {
+        pending_requests.remove(storage, &requester)?;
+    }
 
-            // Save the updated information
-            let mut users_store = PrefixedStorage::new(deps.storage, PREFIX_USERS);
-            let _saved_result = save(&mut users_store, file_metadata.owner.as_bytes(), &user_info);
+    // Claim codes of the file
+    let file_claim_codes = FILE_CLAIM_CODES.add_suffix(&file_key);
+    let claim_keys = file_claim_codes.iter_keys(storage)?.collect::<StdResult<Vec<[u8; 32]>>>()?;
+    for claim_key in claim_keys {
+        CLAIM_CODES.remove(storage, &claim_key)?;
+        file_claim_codes.remove(storage, &claim_key)?;
+    }
 
-        }
-    };
+    PENDING_OWNERS.remove(storage, &file_key)?;
 
-    // Update file information
-    let mut file_metadata_store = PrefixedStorage::new(deps.storage, PREFIX_FILES_METADATA);
-    save(&mut file_metadata_store, &file_key, &file_metadata)?;
+    // Remove the payload and the metadata
+    let mut file_storage = PrefixedStorage::new(storage, PREFIX_FILES);
+    remove(&mut file_storage, &file_key);
 
+    let mut file_metadata_store = PrefixedStorage::new(storage, PREFIX_FILES_METADATA);
+    remove(&mut file_metadata_store, &file_key);
 
     Ok(())
 }
 
 
-/// Read the data from the storage
-pub fn load_file(deps: Deps, key: String) -> StdResult<String> {
+/// List the users seeing a file directly, through a group or through a folder.
+fn _file_audience(storage: &dyn Storage, file_metadata: &FileMetadata) -> Vec<Addr> {
+    let mut audience = file_metadata.viewers.clone();
 
-    // Decode the key
-    let extracted_key = match hex::decode(key) {
-        Ok(k) => k,
-        _ => return Err(StdError::NotFound { kind: String::from("Invalid file key.") }),
-    };
-
-    // Try to load the file
-    let files_store = ReadonlyPrefixedStorage::new(deps.storage, PREFIX_FILES);
-    let loaded_payload: Option<FileState> = may_load(&files_store, &extracted_key)?;
+    for group_key in &file_metadata.groups {
+        if let Some(group) = GROUPS.get(storage, group_key) {
+            audience.extend(group.members);
+        }
+    }
 
-    let payload: String = match loaded_payload {
-        Some(file_state) => file_state.payload,
-        _ => return Err(StdError::NotFound { kind: String::from("Error when loading the file.") }),
-    };
+    if let Some(folder_key) = file_metadata.folder {
+        for (_, folder) in _folder_ancestors(storage, folder_key) {
+            audience.push(folder.owner);
+            audience.extend(folder.viewers);
+        }
+    }
 
-    Ok(payload)
+    if !audience.contains(&file_metadata.owner) {
+        audience.push(file_metadata.owner.clone());
+    }
+    audience.sort();
+    audience.dedup();
+    audience
 }
 
 
-#[entry_point]
-pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {    
-    match msg {
-        QueryMsg::GetContractKey {} => to_binary(&query_key(deps)?),
-        QueryMsg::WithPermit { permit, query } => permit_queries(deps, permit, query)
+/// Check the information given by the owner of a file.
+///
+/// The information is loaded with the metadata on each access to the file, so
+/// its size is bounded.
+fn _validate_file_info(info: &FileInfo) -> Result<(), ContractError> {
+    if info.name.as_ref().is_some_and(|name| name.len() > MAX_FILE_NAME_LENGTH) {
+        return Err(ContractError::CustomError { val: format!("The name cannot exceed {} bytes", MAX_FILE_NAME_LENGTH) });
     }
-}
 
-fn query_key(deps: Deps) -> StdResult<ContractKeyResponse> {
-    let contract_keys = CONTRACT_KEYS.load(deps.storage)?;
-    Ok(ContractKeyResponse {
-        public_key: contract_keys.public_key,
-    })
+    if info.mime_type.as_ref().is_some_and(|mime_type| mime_type.len() > MAX_FILE_MIME_TYPE_LENGTH) {
+        return Err(ContractError::CustomError { val: format!("The mime type cannot exceed {} bytes", MAX_FILE_MIME_TYPE_LENGTH) });
+    }
+
+    if info.tags.len() > MAX_FILE_TAGS {
+        return Err(ContractError::CustomError { val: format!("A file cannot have more than {} tags", MAX_FILE_TAGS) });
+    }
+
+    if info.tags.iter().any(|tag| tag.len() > MAX_FILE_TAG_LENGTH) {
+        return Err(ContractError::CustomError { val: format!("A tag cannot exceed {} bytes", MAX_FILE_TAG_LENGTH) });
+    }
+
+    if let Some(sha256) = &info.sha256 {
+        let is_digest = hex::decode(sha256).map_or(false, |digest| digest.len() == 32);
+        if !is_digest {
+            return Err(ContractError::CustomError { val: String::from("The sha256 must be a hex encoded SHA-256 digest") });
+        }
+    }
+
+    Ok(())
 }
 
-fn permit_queries(deps: Deps, permit: Permit, query: QueryWithPermit) -> Result<Binary, StdError> {
-    
-    // Verify the account through the permit
-    let contract_address = CONFIG.load(deps.storage)?.contract_address;
-    let account = match _verify_permit(deps, permit, contract_address) {
-        Ok(account) => account,
-        Err(e) => panic!("Error {:?}", e),
+
+/// Add a version to the history of a file.
+fn add_file_version(
+    storage: &mut dyn Storage, 
+    block: &BlockInfo, 
+    file_key: [u8; 32], 
+    version: u32, 
+    payload: String, 
+    author: &Addr
+) -> StdResult<()> {
+    let file_version = FileVersion {
+        payload,
+        author: author.clone(),
+        created_at: Some(block.time),
     };
+    FILE_VERSIONS.add_suffix(&file_key).insert(storage, &version, &file_version)
+}
 
-    // Permit validated! We can now execute the query.
-    match query {
-        QueryWithPermit::GetFileIds {} => {
-            // Get user file
-            to_binary(&query_file_ids(deps, account)?)
+
+/// Get the role of a user for the given file, if any.
+///
+/// An expired access is considered as no access.
+pub fn get_file_role(
+    storage: &dyn Storage, 
+    block: &BlockInfo, 
+    file_key: [u8; 32], 
+    user: &Addr
+) -> Option<FileRole> {
+    match FILE_PERMISSIONS.get(storage, &(file_key, user.clone())) {
+        Some(permission) => match permission.expires_at {
+            Some(expiration) if expiration.is_expired(block) => None,
+            _ => Some(permission.role),
         },
-        QueryWithPermit::GetFileContent { file_id } => {
+        None => None,
+    }
+}
 
-            // Decode the file key
-            let key = match hex::decode(&file_id) {
-                Ok(key) => key,
-                _ => return Err(StdError::NotFound { kind: String::from("Invalid key.") })
-            };
-            let u8_key: [u8; 32] = key.try_into().unwrap();
 
-            // Check the permission - whitelisted
-            let whitelisted = FILE_PERMISSIONS.get(deps.storage, &(u8_key, account));
+/// Get the role of a user for the given file, including the access given
+/// through the groups of the user.
+///
+/// Groups only give a viewing access.
+pub fn get_user_file_role(
+    storage: &dyn Storage, 
+    block: &BlockInfo, 
+    file_key: [u8; 32], 
+    user: &Addr
+) -> Option<FileRole> {
 
-            match whitelisted {
-                Some(authorized) => {
-                    if !authorized {
-                        return Err(StdError::generic_err(format!(
-                            "Unauthorized access for the given file."
-                        )));
-                    }
-                },
-                _ => {
-                    return Err(StdError::generic_err(format!(
-                        "Unauthorized access for the given file."
-                    )));
-                }
-            };
+    let role = get_file_role(storage, block, file_key, user);
+    if role.is_some() {
+        return role;
+    }
 
-            // Load the file
-            let file_content: String = load_file(deps, file_id)?;
-            let response = FilePayloadResponse {
-                payload: file_content,
-            };
+    // Check the groups having access to the file
+    let file_metadata_store = ReadonlyPrefixedStorage::new(storage, PREFIX_FILES_METADATA);
+    let loaded_metadata: Option<FileMetadata> = may_load(&file_metadata_store, &file_key).ok().flatten();
 
-            to_binary(&response)
+    match loaded_metadata {
+        Some(metadata) if metadata.groups.iter().any(|group_key| is_group_member(storage, *group_key, user)) => {
+            Some(FileRole::Viewer)
         },
-        QueryWithPermit::GetFileAccess { file_id } => {
-
-            // Extract the key
-            let key = match hex::decode(&file_id) {
-                Ok(key) => key,
-                _ => return Err(StdError::NotFound { kind: String::from("Invalid key.") })
-            };
-            let u8_key: [u8; 32] = key.try_into().unwrap();
+        // Check the folders containing the file
+        Some(FileMetadata { folder: Some(folder_key), .. }) if has_folder_access(storage, folder_key, user) => {
+            Some(FileRole::Viewer)
+        },
+        _ => None,
+    }
+}
 
-            // Get the file owner    
-            let file_metadata_store = ReadonlyPrefixedStorage::new(deps.storage, PREFIX_FILES_METADATA);
-            let loaded_metadata: FileMetadata = may_load(&file_metadata_store, &u8_key)?.unwrap();
 
-            // Check the input user is the owner
-            if loaded_metadata.owner != account {
-                return Err(StdError::generic_err(format!(
-                    "Unauthorized access for the given file."
-                )));
-            };
+/// Set the role of a user for the given file.
+///
+/// If the user does not have access to the file yet, the file is added to the
+/// user file list and the user to the file viewers.
+fn set_file_role(
+    storage: &mut dyn Storage,
+    file_key: [u8; 32],
+    user: &Addr,
+    role: FileRole,
+    expires_at: Option<Expiration>,
+    file_metadata: &mut FileMetadata
+) -> StdResult<()> {
+
+    // An expired access is still referenced in the lists
+    if FILE_PERMISSIONS.get(storage, &(file_key, user.clone())).is_none() {
+        // Add the file in the list of user view
+        let mut users_store = PrefixedStorage::new(storage, PREFIX_USERS);
+        let loaded_payload: Option<UserInfo> = may_load(&users_store, user.as_bytes())?;
+
+        // The user can already exists or not.
+        let mut user_info = match loaded_payload {
+            Some(user_info) => user_info,
+            None => UserInfo {
+                files: Vec::new()
+            }
+        };
 
-            let file_access_response = FileAccessResponse {
-                owner: loaded_metadata.owner,
-                viewers: loaded_metadata.viewers
-            };
+        user_info.files.push(file_key);
+        save(&mut users_store, user.as_bytes(), &user_info)?;
 
-            to_binary(&file_access_response)
-        }
+        // Update the file metadata
+        file_metadata.viewers.push(user.clone());
     }
+
+    let permission = FilePermission {
+        role,
+        expires_at,
+    };
+    FILE_PERMISSIONS.insert(storage, &(file_key, user.clone()), &permission)
 }
 
-/// Return the file ids given a user.
-/// We need to verify with a permit that only the given account is the one that can retrieve the data.
-fn query_file_ids(deps: Deps, account: Addr) -> StdResult<FileIdsResponse> {
 
-    // Get user storage
-    let users_store = ReadonlyPrefixedStorage::new(deps.storage, PREFIX_USERS);
-    let loaded_payload: StdResult<Option<UserInfo>> = may_load(&users_store, account.as_bytes());
+/// Remove every right of a user for the given file.
+fn remove_file_role(
+    storage: &mut dyn Storage,
+    file_key: [u8; 32],
+    user: &Addr,
+    file_metadata: &mut FileMetadata
+) -> StdResult<()> {
 
-    match loaded_payload {
-        Ok(Some(user_info)) => {
+    // Remove permission and the key wrapped for the user
+    FILE_PERMISSIONS.remove(storage, &(file_key, user.clone()))?;
+    FILE_KEYS.add_suffix(&file_key).remove(storage, user)?;
 
-            let key_to_string : Vec<String> = user_info.files.iter().map(|&bytes_key| {
-                hex::encode(&bytes_key)
-            }).collect();
+    // Remove the file from the user list
+    let mut users_store = PrefixedStorage::new(storage, PREFIX_USERS);
+    let loaded_payload: Option<UserInfo> = may_load(&users_store, user.as_bytes())?;
 
-            Ok(FileIdsResponse { file_ids: key_to_string })
-        }
-        Ok(None) => Ok(FileIdsResponse { file_ids: Vec::new() }),
-        Err(error) => Err(StdError::generic_err(format!(
-            "Error when loading file from storage: {:?}", error
-        )))
+    if let Some(mut user_info) = loaded_payload {
+        user_info.files.retain(|x| *x != file_key);
+        save(&mut users_store, user.as_bytes(), &user_info)?;
     }
+
+    // Remove the user from the list
+    file_metadata.viewers.retain(|x| x != user);
+
+    Ok(())
 }
 
 
-#[cfg(test)]
-mod tests {
-    
-    use super::*;
+/// Store the key of a file wrapped for each given user.
+///
+/// The content of the file is shared by all the users, but each user has his own 
+/// wrapped key. Any user seeing the file can get one, including through a group or
+/// a folder. A user removed from the file loses his wrapped key. A user losing his
+/// access through a group or a folder keeps it stored, but it is only served with
+/// the file, to the users having access, until the next re-key.
+fn add_file_keys(
+    storage: &mut dyn Storage,
+    block: &BlockInfo,
+    file_key: [u8; 32],
+    keys: Vec<WrappedFileKey>
+) -> Result<(), ContractError> {
 
-    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
-    use cosmwasm_std::{coins, from_binary};
-    use secret_toolkit::permit::{PermitParams, PermitSignature, PubKey, TokenPermissions};
-    use secret_toolkit::serialization::Serde;
+    for wrapped_file_key in keys {
+        let user = _normalize_address(wrapped_file_key.user);
 
-    /// Instanciate a new smart contract
-    fn setup_contract(deps: DepsMut) {
-        // Instanciate our Secret Contract
-        let msg = InstantiateMsg {};
-        let info = mock_info("creator", &coins(0, ""));
-        let response = instantiate(deps, mock_env(), info, msg).unwrap();
-        assert_eq!(0, response.messages.len());
+        if get_user_file_role(storage, block, file_key, &user).is_none() {
+            return Err(ContractError::CustomError { val: format!("The user {} has no access to the file", user) });
+        }
+
+        FILE_KEYS.add_suffix(&file_key).insert(storage, &user, &wrapped_file_key.wrapped_key)?;
     }
 
-    /// Generate a valid address and a valid permit
-    fn generate_user_1(deps: DepsMut) -> (Addr, Permit) {
+    Ok(())
+}
 
-        // cosmos2contract
-        let token_address = CONFIG.load(deps.storage).unwrap().contract_address;
 
-        let user_address = "secret1f0pcrxqsgm3ss598nreq3lryv45xa8w7cq55df";
-        let permit_name = "SECRET_DOCUMENT_PERMIT_3812";
-        let chain_id = "secret-4";
-        let pub_key = "A1kUPltujGDLo0vr5M/26mdQF+9cnx8MROF/bL772HDc";
-        let signature = "xt42IhS4k4sb2P1ZNIK6zDWfgaNGB/p9SnHyY9OVNXw1ioGmtMkSVQ/3iBR9gfdFf4K98vyCazg+95n3UrWSNA==";
+/// Replace the payload of a file with a new one, encrypted with a new key.
+///
+/// A user removed from a file may have kept the previous key. When the access is
+/// revoked with a re-key, the payload is not served until the owner replaces it.
+/// The keys wrapped for the previous payload and the previous versions, encrypted
+/// with the previous key, are deleted.
+fn rekey_file(
+    storage: &mut dyn Storage,
+    block: &BlockInfo,
+    file_key: [u8; 32],
+    author: &Addr,
+    payload: String,
+    keys: Vec<WrappedFileKey>
+) -> Result<(), ContractError> {
 
-        let user_permit = Permit {
-            params: PermitParams {
-                allowed_tokens: vec![String::from(token_address)],
-                permit_name: permit_name.to_string(),
-                chain_id: chain_id.to_string(),
-                permissions: vec![TokenPermissions::Owner],
-            },
-            signature: PermitSignature {
-                pub_key: PubKey {
-                    r#type: "tendermint/PubKeySecp256k1".to_string(),
-                    value: Binary::from_base64(pub_key).unwrap(),
-                },
-                signature: Binary::from_base64(signature).unwrap(),
-            }
-        };
+    let version = update_file(storage, block, file_key, payload, author)?;
 
-        let user_address = deps.api.addr_validate(user_address).unwrap();
+    // Delete the previous versions, the removed users could decrypt them
+    let file_versions = FILE_VERSIONS.add_suffix(&file_key);
+    let previous_versions = file_versions.iter_keys(storage)?.collect::<StdResult<Vec<u32>>>()?;
+    for previous_version in previous_versions.into_iter().filter(|previous_version| *previous_version != version) {
+        file_versions.remove(storage, &previous_version)?;
+    }
 
-        return (user_address, user_permit)
+    // Delete the keys wrapped for the previous payload
+    let file_keys = FILE_KEYS.add_suffix(&file_key);
+    let users = file_keys.iter_keys(storage)?.collect::<StdResult<Vec<Addr>>>()?;
+    for user in users {
+        file_keys.remove(storage, &user)?;
     }
 
-    fn generate_user_2(deps: DepsMut) -> (Addr, Permit) {
-        // https://permits.scrtlabs.com/
+    let mut file_metadata_store = PrefixedStorage::new(storage, PREFIX_FILES_METADATA);
+    let mut file_metadata: FileMetadata = load(&file_metadata_store, &file_key)?;
+    file_metadata.pending_rekey = false;
+    file_metadata.rotated_at = Some(block.time);
+    save(&mut file_metadata_store, &file_key, &file_metadata)?;
 
-        let token_address = CONFIG.load(deps.storage).unwrap().contract_address;
+    add_file_keys(storage, block, file_key, keys)
+}
 
-        let user_address = "secret1u69n2nxq3495t8lw2usn0qgg685ad7d9j0w6lq";
-        let permit_name = "SECRET_DOCUMENT_PERMIT_7616";
-        let chain_id = "secret-4";
-        let pub_key = "A7bPvqlFfe3BccQzfpbRm12RJGnvEy3K+SUDviYs5qWz";
-        let signature = "Znn1BdQreny83DX+sy9JjZMxeU8LtMlnBkt4YKp/FOkZvu6afB5wm+H/d8sThA/DdBkUBbTPk2bD6UgPsuifUA==";
 
+/// Update file permissions
+///
+/// The owner of the file cannot be changed here, see `transfer_file_ownership`.
+pub fn update_file_access(
+    deps: DepsMut,
+    env: &Env,
+    file_key: [u8; 32], 
+    add_viewing: Vec<Addr>, 
+    delete_viewing: Vec<Addr>, 
+    roles: Vec<RoleAssignment>
+) -> Result<(), ContractError> {
 
-        let user_permit = Permit {
-            params: PermitParams {
-                allowed_tokens: vec![String::from(token_address)],
-                permit_name: permit_name.to_string(),
-                chain_id: chain_id.to_string(),
-                permissions: vec![TokenPermissions::Owner],
-            },
-            signature: PermitSignature {
-                pub_key: PubKey {
-                    r#type: "tendermint/PubKeySecp256k1".to_string(),
-                    value: Binary::from_base64(pub_key).unwrap(),
-                },
-                signature: Binary::from_base64(signature).unwrap(),
-            }
-        };
+    // Load the file metadata
+    let file_metadata_store = PrefixedStorage::new(deps.storage, PREFIX_FILES_METADATA);
+    let mut file_metadata: FileMetadata = load(&file_metadata_store, &file_key)?;
 
-        let user_address = deps.api.addr_validate(user_address).unwrap();
+    // Add all viewing access
+    for user_add in &add_viewing {
 
-        return (user_address, user_permit)
-    }
+        // Keep the current role if the user already has access
+        if get_file_role(deps.storage, &env.block, file_key, user_add).is_none() {
+            set_file_role(deps.storage, file_key, user_add, FileRole::Viewer, None, &mut file_metadata)?;
+        }
 
-    fn _query_contract_pubic_key(deps: Deps) -> ContractKeyResponse {
-        let query_msg = QueryMsg::GetContractKey {};
-        let response = query(deps, mock_env(), query_msg).unwrap();
-        let key_response: ContractKeyResponse = from_binary(&response).unwrap();
-        key_response
-    }
+    };
 
-    fn _generate_local_public_private_key(env: Env) -> (Vec<u8>, Vec<u8>) {
-        // Generate public/private key locally
-        let rng = env.block.random.unwrap().0;
-        let secp = Secp256k1::new();
+    // Set the roles
+    for assignment in &roles {
 
-        let private_key = SecretKey::from_slice(&rng).unwrap();
-        let private_key_string = private_key.display_secret().to_string();
-        let private_key_bytes = hex::decode(private_key_string).unwrap();
+        if assignment.role == FileRole::Owner {
+            return Err( ContractError::CustomError { val: String::from("The owner role can only be given by changing the owner") });
+        }
 
-        let public_key = PublicKey::from_secret_key(&secp, &private_key);
-        let public_key_bytes = public_key.serialize().to_vec();
+        if assignment.address == file_metadata.owner {
+            return Err( ContractError::CustomError { val: String::from("Cannot change the role of the owner") });
+        }
 
-        return (public_key_bytes, private_key_bytes);
-    }
+        set_file_role(
+            deps.storage, 
+            file_key, 
+            &assignment.address, 
+            assignment.role, 
+            assignment.expires_at.clone(), 
+            &mut file_metadata
+        )?;
+    };
 
-    fn _encrypt_with_share_secret(
-        local_private_key: Vec<u8>, 
-        contract_public_key: Vec<u8>, 
-        message_to_encrypt: &Vec<u8>
-    ) -> Vec<u8> {
-        let my_private_key = SecretKey::from_slice(&local_private_key)
-            .map_err(|e| ContractError::CustomError {
-                val: format!("Invalid private key: {}", e),
-            })
-            .unwrap();
+    // Delete viewing access
+    for user_delete in &delete_viewing {
 
-        let other_public_key = PublicKey::from_slice(contract_public_key.as_slice())
-            .map_err(|e| ContractError::CustomError {
-                val: format!("Invalid public key: {}", e),
-            })
-            .unwrap();
+        // Check if the user has a viewing right, even an expired one
+        if FILE_PERMISSIONS.get(deps.storage, &(file_key, user_delete.clone())).is_some() {
 
-        let shared_secret = SharedSecret::new(&other_public_key, &my_private_key);
-        let key = shared_secret.secret_bytes();
+            if user_delete == &file_metadata.owner {
+                return Err( ContractError::CustomError { val: String::from("Cannot remove viewing right from the owner") });
+            }
 
-        // Encrypt our payload
-        let ad_data: &[&[u8]] = &[];
-        let ad = Some(ad_data);
-        let ad = ad.unwrap_or(&[&[]]);
+            remove_file_role(deps.storage, file_key, user_delete, &mut file_metadata)?;
+        }
 
-        let mut cipher = Aes128Siv::new(GenericArray::clone_from_slice(&key));
-        let encrypt_message = cipher
-            .encrypt(ad, message_to_encrypt)
-            .map_err(|_e| ContractError::EncryptionError)
-            .unwrap();
+    };
 
-        return encrypt_message;
-    }
+    // Update file information
+    let mut file_metadata_store = PrefixedStorage::new(deps.storage, PREFIX_FILES_METADATA);
+    save(&mut file_metadata_store, &file_key, &file_metadata)?;
 
-    /// Create an execute message given a file and a user permit
-    fn _create_evm_message(
-        deps: Deps,
-        file: &String, 
-        permit: &Permit
-    ) -> ExecuteMsg {
 
-        // Create the message for storing a new file
-        let message = &Json::serialize(
-            &ExecutePermitMsg::WithPermit { 
-                permit: permit.clone(), 
-                execute: ExecuteMsgAction::StoreNewFile { 
-                    payload: file.clone() 
-                } 
-            }
+    Ok(())
+}
+
+
+/// Move the rights given to the Ethereum address of a user to his account.
+///
+/// Files and groups can be shared with an Ethereum address before its owner
+/// uses the contract. Once the user proves with a permit that he owns both
+/// the Ethereum address and the secret account, the rights are attached to
+/// his account. If the user already has a role on a file, the highest one is kept.
+pub fn resolve_ethereum_grants(deps: DepsMut, ethereum_address: &Addr, account: &Addr) -> StdResult<()> {
+
+    // Files shared with the Ethereum address
+    let mut users_store = PrefixedStorage::new(deps.storage, PREFIX_USERS);
+    let loaded_payload: Option<UserInfo> = may_load(&users_store, ethereum_address.as_bytes())?;
+
+    if let Some(user_info) = loaded_payload {
+        remove(&mut users_store, ethereum_address.as_bytes());
+
+        for file_key in user_info.files {
+            let file_metadata_store = PrefixedStorage::new(deps.storage, PREFIX_FILES_METADATA);
+            let loaded_metadata: Option<FileMetadata> = may_load(&file_metadata_store, &file_key)?;
+
+            let mut file_metadata = match loaded_metadata {
+                Some(file_metadata) => file_metadata,
+                None => continue,
+            };
+
+            if let Some(permission) = FILE_PERMISSIONS.get(deps.storage, &(file_key, ethereum_address.clone())) {
+                FILE_PERMISSIONS.remove(deps.storage, &(file_key, ethereum_address.clone()))?;
+                file_metadata.viewers.retain(|x| x != ethereum_address);
+
+                // The key wrapped for the Ethereum address can be unwrapped with the same key pair
+                let file_keys = FILE_KEYS.add_suffix(&file_key);
+                if let Some(wrapped_key) = file_keys.get(deps.storage, ethereum_address) {
+                    file_keys.remove(deps.storage, ethereum_address)?;
+                    if file_keys.get(deps.storage, account).is_none() {
+                        file_keys.insert(deps.storage, account, &wrapped_key)?;
+                    }
+                }
+
+                let current_permission = FILE_PERMISSIONS.get(deps.storage, &(file_key, account.clone()));
+                if current_permission.map_or(true, |current| current.role < permission.role) {
+                    set_file_role(
+                        deps.storage, 
+                        file_key, 
+                        account, 
+                        permission.role, 
+                        permission.expires_at, 
+                        &mut file_metadata
+                    )?;
+                }
+            }
+
+            let mut file_metadata_store = PrefixedStorage::new(deps.storage, PREFIX_FILES_METADATA);
+            save(&mut file_metadata_store, &file_key, &file_metadata)?;
+        }
+    }
+
+    // Groups having the Ethereum address as member
+    if let Some(group_keys) = USER_GROUPS.get(deps.storage, ethereum_address) {
+        USER_GROUPS.remove(deps.storage, ethereum_address)?;
+
+        for group_key in group_keys {
+            if let Some(mut group) = GROUPS.get(deps.storage, &group_key) {
+                group.members.retain(|x| x != ethereum_address);
+                if !group.members.contains(account) {
+                    group.members.push(account.clone());
+                }
+                GROUPS.insert(deps.storage, &group_key, &group)?;
+
+                add_group_to_user(deps.storage, account, group_key)?;
+            }
+        }
+    }
+
+    // Folders shared with the Ethereum address
+    if let Some(folder_keys) = USER_FOLDERS.get(deps.storage, ethereum_address) {
+        USER_FOLDERS.remove(deps.storage, ethereum_address)?;
+
+        for folder_key in folder_keys {
+            if let Some(mut folder) = FOLDERS.get(deps.storage, &folder_key) {
+                folder.viewers.retain(|x| x != ethereum_address);
+                if folder.owner != *account && !folder.viewers.contains(account) {
+                    folder.viewers.push(account.clone());
+                }
+                FOLDERS.insert(deps.storage, &folder_key, &folder)?;
+
+                add_folder_to_user(deps.storage, account, folder_key)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+
+/// Transfer the ownership of a file to a new owner.
+///
+/// The previous owner keeps a viewing access. Any pending ownership transfer
+/// is cleared.
+pub fn transfer_file_ownership(
+    deps: DepsMut,
+    file_key: [u8; 32],
+    new_owner: Addr
+) -> Result<(), ContractError> {
+
+    // Load the file metadata
+    let file_metadata_store = PrefixedStorage::new(deps.storage, PREFIX_FILES_METADATA);
+    let mut file_metadata: FileMetadata = load(&file_metadata_store, &file_key)?;
+
+    let previous_owner = file_metadata.owner.clone();
+    file_metadata.owner = new_owner.clone();
+
+    set_file_role(deps.storage, file_key, &previous_owner, FileRole::Viewer, None, &mut file_metadata)?;
+    set_file_role(deps.storage, file_key, &new_owner, FileRole::Owner, None, &mut file_metadata)?;
+
+    PENDING_OWNERS.remove(deps.storage, &file_key)?;
+
+    // Update file information
+    let mut file_metadata_store = PrefixedStorage::new(deps.storage, PREFIX_FILES_METADATA);
+    save(&mut file_metadata_store, &file_key, &file_metadata)?;
+
+    Ok(())
+}
+
+
+/// Claim a viewing access to a file with the preimage of a registered claim code.
+pub fn claim_file_access(
+    deps: DepsMut,
+    env: &Env,
+    account: Addr,
+    preimage: String
+) -> Result<(), ContractError> {
+
+    // Hash the preimage
+    let mut hasher = Sha256::new();
+    hasher.update(preimage.as_bytes());
+    let claim_key: [u8; 32] = hasher.finalize().into();
+
+    // Check that the code can still be claimed
+    let mut claim_code = match CLAIM_CODES.get(deps.storage, &claim_key) {
+        Some(claim_code) => claim_code,
+        None => return Err(ContractError::InvalidClaimCode)
+    };
+
+    let expired = claim_code.expires_at.as_ref().is_some_and(|expiration| expiration.is_expired(&env.block));
+    let exhausted = claim_code.max_claims.is_some_and(|max_claims| claim_code.claims >= max_claims);
+    if expired || exhausted {
+        return Err(ContractError::InvalidClaimCode);
+    }
+
+    // Nothing to claim if the user already has access
+    if get_file_role(deps.storage, &env.block, claim_code.file_key, &account).is_some() {
+        return Ok(());
+    }
+
+    claim_code.claims += 1;
+    CLAIM_CODES.insert(deps.storage, &claim_key, &claim_code)?;
+
+    // Give a viewing access to the user
+    update_file_access(
+        deps,
+        env,
+        claim_code.file_key,
+        Vec::from([account]),
+        Vec::new(),
+        Vec::new()
+    )?;
+
+    Ok(())
+}
+
+
+/// Answer a pending access request
+fn answer_access_request(
+    storage: &mut dyn Storage,
+    file_key: [u8; 32],
+    requester: &Addr,
+    status: AccessRequestStatus
+) -> Result<(), ContractError> {
+
+    let file_requests = ACCESS_REQUESTS.add_suffix(&file_key);
+
+    let mut access_request = match file_requests.get(storage, requester) {
+        Some(access_request) if access_request.status == AccessRequestStatus::Pending => access_request,
+        _ => return Err(ContractError::NoPendingAccessRequest)
+    };
+
+    access_request.status = status;
+    file_requests.insert(storage, requester, &access_request)?;
+    PENDING_ACCESS_REQUESTS.add_suffix(&file_key).remove(storage, requester)?;
+
+    Ok(())
+}
+
+
+/// Update the groups having a viewing access to the file
+///
+/// A group can only be added by its owner or one of its members, so the
+/// files cannot be pushed to the members of any group.
+pub fn update_file_groups(
+    deps: DepsMut,
+    account: &Addr,
+    file_key: [u8; 32],
+    add_groups: Vec<String>,
+    delete_groups: Vec<String>
+) -> Result<(), ContractError> {
+
+    if add_groups.is_empty() && delete_groups.is_empty() {
+        return Ok(());
+    }
+
+    // Check all the added groups before any change
+    let mut added_groups: Vec<([u8; 32], Group)> = Vec::new();
+    for group_id in &add_groups {
+        let group_key = hex::decode(group_id)?;
+        let group_key: [u8; 32] = group_key.try_into()
+            .map_err(|_| ContractError::InvalidGroupID)?;
+
+        let group = match GROUPS.get(deps.storage, &group_key) {
+            Some(group) => group,
+            None => return Err(ContractError::InvalidGroupID)
+        };
+
+        if group.owner != *account && !group.members.contains(account) {
+            return Err(ContractError::UnauthorizedGroupAccess);
+        }
+
+        added_groups.push((group_key, group));
+    }
+
+    // Load the file metadata
+    let file_metadata_store = PrefixedStorage::new(deps.storage, PREFIX_FILES_METADATA);
+    let mut file_metadata: FileMetadata = load(&file_metadata_store, &file_key)?;
+
+    // Add the groups
+    for (group_key, mut group) in added_groups {
+        if !file_metadata.groups.contains(&group_key) {
+            file_metadata.groups.push(group_key);
+
+            group.files.push(file_key);
+            GROUPS.insert(deps.storage, &group_key, &group)?;
+        }
+    }
+
+    // Delete the groups
+    for group_id in &delete_groups {
+        let group_key = hex::decode(group_id)?;
+        let group_key: [u8; 32] = group_key.try_into()
+            .map_err(|_| ContractError::InvalidGroupID)?;
+
+        if let Some(mut group) = GROUPS.get(deps.storage, &group_key) {
+            group.files.retain(|x| *x != file_key);
+            GROUPS.insert(deps.storage, &group_key, &group)?;
+        }
+
+        file_metadata.groups.retain(|x| *x != group_key);
+    }
+
+    // Update file information
+    let mut file_metadata_store = PrefixedStorage::new(deps.storage, PREFIX_FILES_METADATA);
+    save(&mut file_metadata_store, &file_key, &file_metadata)?;
+
+    Ok(())
+}
+
+
+/// Check if a user is a member of the given group
+pub fn is_group_member(storage: &dyn Storage, group_key: [u8; 32], user: &Addr) -> bool {
+    match GROUPS.get(storage, &group_key) {
+        Some(group) => group.members.contains(user),
+        None => false,
+    }
+}
+
+
+/// Add a group to the list of groups of a user
+fn add_group_to_user(storage: &mut dyn Storage, user: &Addr, group_key: [u8; 32]) -> StdResult<()> {
+    let mut user_groups = USER_GROUPS.get(storage, user).unwrap_or_default();
+    if !user_groups.contains(&group_key) {
+        user_groups.push(group_key);
+        USER_GROUPS.insert(storage, user, &user_groups)?;
+    }
+    Ok(())
+}
+
+
+/// Remove a group from the list of groups of a user
+fn remove_group_from_user(storage: &mut dyn Storage, user: &Addr, group_key: [u8; 32]) -> StdResult<()> {
+    if let Some(mut user_groups) = USER_GROUPS.get(storage, user) {
+        user_groups.retain(|x| *x != group_key);
+        USER_GROUPS.insert(storage, user, &user_groups)?;
+    }
+    Ok(())
+}
+
+
+/// Create a new group of users.
+///
+/// The creator of the group is its owner and is also a member of the group.
+pub fn create_group(
+    deps: DepsMut, 
+    block: &BlockInfo, 
+    owner: Addr, 
+    name: String, 
+    members: Vec<Addr>
+) -> Result<String, ContractError> {
+
+    if name.len() > MAX_GROUP_NAME_LENGTH {
+        return Err(ContractError::CustomError { val: format!("The name of a group cannot exceed {} bytes", MAX_GROUP_NAME_LENGTH) });
+    }
+
+    let mut group_members: Vec<Addr> = Vec::new();
+    for member in std::iter::once(&owner).chain(members.iter()) {
+        if !group_members.contains(member) {
+            group_members.push(member.clone());
+        }
+    }
+    _check_group_members(&group_members)?;
+
+    // Get a unique id
+    let key: [u8; 32] = next_unique_id(deps.storage, block)?;
+
+    for member in &group_members {
+        add_group_to_user(deps.storage, member, key)?;
+    }
+
+    let group = Group {
+        name,
+        owner,
+        members: group_members,
+        files: Vec::new(),
+    };
+
+    GROUPS.insert(deps.storage, &key, &group)?;
+
+    // Return the key of the group
+    Ok(hex::encode(&key))
+}
+
+
+/// Update the members of a group.
+///
+/// As the access given by a group is checked against its members, the access
+/// to every file shared with the group is updated at once.
+pub fn update_group_members(
+    deps: DepsMut,
+    account: Addr,
+    group_key: [u8; 32],
+    add_members: Vec<Addr>,
+    remove_members: Vec<Addr>
+) -> Result<(), ContractError> {
+
+    let mut group = match GROUPS.get(deps.storage, &group_key) {
+        Some(group) => group,
+        None => return Err(ContractError::InvalidGroupID)
+    };
+
+    // Only the owner of the group can manage its members
+    if group.owner != account {
+        return Err(ContractError::UnauthorizedGroupAccess);
+    }
+
+    // Check the new members before any change
+    let mut members = group.members.clone();
+    for member in &add_members {
+        if !members.contains(member) {
+            members.push(member.clone());
+        }
+    }
+    members.retain(|x| !remove_members.contains(x));
+    _check_group_members(&members)?;
+
+    for member in members.iter().filter(|member| !group.members.contains(member)) {
+        add_group_to_user(deps.storage, member, group_key)?;
+    }
+    for member in group.members.iter().filter(|member| !members.contains(member)) {
+        remove_group_from_user(deps.storage, member, group_key)?;
+    }
+
+    group.members = members;
+    GROUPS.insert(deps.storage, &group_key, &group)?;
+
+    Ok(())
+}
+
+
+/// Check the size of the members of a group.
+fn _check_group_members(members: &[Addr]) -> Result<(), ContractError> {
+    if members.len() > MAX_GROUP_MEMBERS {
+        return Err(ContractError::CustomError { val: format!("A group cannot have more than {} members", MAX_GROUP_MEMBERS) });
+    }
+
+    Ok(())
+}
+
+
+/// Return the given folder followed by all its parent folders.
+fn _folder_ancestors(storage: &dyn Storage, folder_key: [u8; 32]) -> Vec<([u8; 32], Folder)> {
+    let mut ancestors: Vec<([u8; 32], Folder)> = Vec::new();
+    let mut current = Some(folder_key);
+
+    while let Some(key) = current {
+        // Stop on a missing folder or a loop
+        if ancestors.iter().any(|(ancestor_key, _)| *ancestor_key == key) {
+            break;
+        }
+        let folder = match FOLDERS.get(storage, &key) {
+            Some(folder) => folder,
+            None => break,
+        };
+        current = folder.parent;
+        ancestors.push((key, folder));
+    }
+
+    ancestors
+}
+
+
+/// Check that a parent folder can receive a sub-folder of the given height
+/// (1 for a folder without sub-folders).
+///
+/// The parents of a folder are walked through on each access, so the nesting
+/// and the number of sub-folders are bounded.
+fn _check_folder_nesting(
+    storage: &dyn Storage, 
+    parent_key: [u8; 32], 
+    parent_folder: &Folder, 
+    height: usize
+) -> Result<(), ContractError> {
+    if parent_folder.folders.len() >= MAX_FOLDER_SUB_FOLDERS {
+        return Err(ContractError::CustomError { val: format!("A folder cannot contain more than {} folders", MAX_FOLDER_SUB_FOLDERS) });
+    }
+
+    if _folder_ancestors(storage, parent_key).len() + height > MAX_FOLDER_DEPTH {
+        return Err(ContractError::CustomError { val: format!("The folders cannot be nested more than {} levels deep", MAX_FOLDER_DEPTH) });
+    }
+
+    Ok(())
+}
+
+
+/// Number of levels of a folder and its sub-folders, 1 for a folder without sub-folders.
+fn _folder_height(storage: &dyn Storage, folder: &Folder) -> usize {
+    let sub_folders_height = folder.folders.iter()
+        .filter_map(|key| FOLDERS.get(storage, key))
+        .map(|sub_folder| _folder_height(storage, &sub_folder))
+        .max()
+        .unwrap_or(0);
+
+    sub_folders_height + 1
+}
+
+
+/// Check if a user can see the content of a folder.
+///
+/// The access is given by the folder itself or by any of its parent folders.
+pub fn has_folder_access(storage: &dyn Storage, folder_key: [u8; 32], user: &Addr) -> bool {
+    _folder_ancestors(storage, folder_key)
+        .iter()
+        .any(|(_, folder)| folder.owner == *user || folder.viewers.contains(user))
+}
+
+
+/// Add a folder to the list of folders of a user
+fn add_folder_to_user(storage: &mut dyn Storage, user: &Addr, folder_key: [u8; 32]) -> StdResult<()> {
+    let mut user_folders = USER_FOLDERS.get(storage, user).unwrap_or_default();
+    if !user_folders.contains(&folder_key) {
+        user_folders.push(folder_key);
+        USER_FOLDERS.insert(storage, user, &user_folders)?;
+    }
+    Ok(())
+}
+
+
+/// Remove a folder from the list of folders of a user
+fn remove_folder_from_user(storage: &mut dyn Storage, user: &Addr, folder_key: [u8; 32]) -> StdResult<()> {
+    if let Some(mut user_folders) = USER_FOLDERS.get(storage, user) {
+        user_folders.retain(|x| *x != folder_key);
+        USER_FOLDERS.insert(storage, user, &user_folders)?;
+    }
+    Ok(())
+}
+
+
+/// Create a new folder, optionally inside a parent folder.
+pub fn create_folder(
+    deps: DepsMut, 
+    block: &BlockInfo, 
+    owner: Addr, 
+    name: String, 
+    parent: Option<[u8; 32]>
+) -> StdResult<String> {
+
+    // Get a unique id
+    let key: [u8; 32] = next_unique_id(deps.storage, block)?;
+
+    let folder = Folder {
+        name,
+        owner: owner.clone(),
+        viewers: Vec::new(),
+        parent,
+        files: Vec::new(),
+        folders: Vec::new(),
+    };
+    FOLDERS.insert(deps.storage, &key, &folder)?;
+
+    // Reference the folder in its parent
+    if let Some(parent_key) = parent {
+        if let Some(mut parent_folder) = FOLDERS.get(deps.storage, &parent_key) {
+            parent_folder.folders.push(key);
+            FOLDERS.insert(deps.storage, &parent_key, &parent_folder)?;
+        }
+    }
+
+    add_folder_to_user(deps.storage, &owner, key)?;
+
+    // Return the key of the folder
+    Ok(hex::encode(&key))
+}
+
+
+/// Update the viewers of a folder.
+///
+/// The viewers of a folder can see all the files inside it and inside its
+/// sub-folders, without being added to each file.
+pub fn update_folder_access(
+    deps: DepsMut,
+    folder_key: [u8; 32],
+    mut folder: Folder,
+    add_viewing: Vec<Addr>,
+    delete_viewing: Vec<Addr>
+) -> Result<(), ContractError> {
+
+    for viewer in &add_viewing {
+        if *viewer != folder.owner && !folder.viewers.contains(viewer) {
+            folder.viewers.push(viewer.clone());
+            add_folder_to_user(deps.storage, viewer, folder_key)?;
+        }
+    }
+
+    for viewer in &delete_viewing {
+        if *viewer == folder.owner {
+            return Err(ContractError::CustomError { val: String::from("Cannot remove viewing right from the owner") });
+        }
+        if folder.viewers.contains(viewer) {
+            folder.viewers.retain(|x| x != viewer);
+            remove_folder_from_user(deps.storage, viewer, folder_key)?;
+        }
+    }
+
+    FOLDERS.insert(deps.storage, &folder_key, &folder)?;
+
+    Ok(())
+}
+
+
+/// Move a file to a folder, or out of its folder.
+///
+/// The users having access to the file through its previous folder lose it,
+/// and the ones of the new folder get it.
+pub fn move_file(
+    deps: DepsMut,
+    file_key: [u8; 32],
+    mut file_metadata: FileMetadata,
+    folder_key: Option<[u8; 32]>
+) -> StdResult<()> {
+
+    if let Some(previous_key) = file_metadata.folder {
+        if let Some(mut previous_folder) = FOLDERS.get(deps.storage, &previous_key) {
+            previous_folder.files.retain(|x| *x != file_key);
+            FOLDERS.insert(deps.storage, &previous_key, &previous_folder)?;
+        }
+    }
+
+    if let Some(new_key) = folder_key {
+        if let Some(mut new_folder) = FOLDERS.get(deps.storage, &new_key) {
+            new_folder.files.push(file_key);
+            FOLDERS.insert(deps.storage, &new_key, &new_folder)?;
+        }
+    }
+
+    file_metadata.folder = folder_key;
+    let mut file_metadata_store = PrefixedStorage::new(deps.storage, PREFIX_FILES_METADATA);
+    save(&mut file_metadata_store, &file_key, &file_metadata)?;
+
+    Ok(())
+}
+
+
+/// Move a folder inside another folder, or make it a root folder.
+pub fn move_folder(
+    deps: DepsMut,
+    folder_key: [u8; 32],
+    mut folder: Folder,
+    parent: Option<[u8; 32]>
+) -> StdResult<()> {
+
+    if let Some(previous_key) = folder.parent {
+        if let Some(mut previous_parent) = FOLDERS.get(deps.storage, &previous_key) {
+            previous_parent.folders.retain(|x| *x != folder_key);
+            FOLDERS.insert(deps.storage, &previous_key, &previous_parent)?;
+        }
+    }
+
+    if let Some(new_key) = parent {
+        if let Some(mut new_parent) = FOLDERS.get(deps.storage, &new_key) {
+            new_parent.folders.push(folder_key);
+            FOLDERS.insert(deps.storage, &new_key, &new_parent)?;
+        }
+    }
+
+    folder.parent = parent;
+    FOLDERS.insert(deps.storage, &folder_key, &folder)?;
+
+    Ok(())
+}
+
+
+/// Revoke a permit of a user.
+///
+/// Once revoked, the permit cannot be used anymore for the queries and the
+/// execute messages.
+pub fn revoke_permit(deps: DepsMut, account: &Addr, permit_name: String) -> StdResult<()> {
+    RevokedPermits::revoke_permit(deps.storage, PREFIX_REVOKED_PERMITS, account.as_str(), &permit_name);
+
+    // Keep the list of the revoked permits of the user
+    let mut revoked_permits = USER_REVOKED_PERMITS.get(deps.storage, account).unwrap_or_default();
+    if !revoked_permits.contains(&permit_name) {
+        revoked_permits.push(permit_name);
+        USER_REVOKED_PERMITS.insert(deps.storage, account, &revoked_permits)?;
+    }
+
+    Ok(())
+}
+
+
+/// Read the data from the storage
+pub fn load_file(deps: Deps, key: String) -> StdResult<String> {
+
+    // Decode the key
+    let extracted_key = match hex::decode(key) {
+        Ok(k) => k,
+        _ => return Err(StdError::NotFound { kind: String::from("Invalid file key.") }),
+    };
+
+    // Try to load the file
+    let files_store = ReadonlyPrefixedStorage::new(deps.storage, PREFIX_FILES);
+    let loaded_payload: Option<FileState> = may_load(&files_store, &extracted_key)?;
+
+    let payload: String = match loaded_payload {
+        Some(file_state) => file_state.payload,
+        _ => return Err(StdError::NotFound { kind: String::from("Error when loading the file.") }),
+    };
+
+    Ok(payload)
+}
+
+
+#[entry_point]
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> Result<Binary, ContractError> {    
+    match msg {
+        QueryMsg::GetContractKey {} => Ok(to_binary(&query_key(deps)?)?),
+        QueryMsg::GetContractStatus {} => Ok(to_binary(&query_contract_status(deps)?)?),
+        QueryMsg::GetAllowedOrigins {} => Ok(to_binary(&query_allowed_origins(deps)?)?),
+        QueryMsg::WithPermit { permit, signature_scheme, query, public_key } => {
+            // No user data can be read while the contract is paused
+            if CONFIG.load(deps.storage)?.reads_paused {
+                return Err(ContractError::ContractPaused)
+            }
+            let response = permit_queries(deps, env, permit, signature_scheme, query)?;
+            match public_key {
+                Some(public_key) => _encrypt_for_user_public_key(deps, response, public_key),
+                None => Ok(response),
+            }
+        },
+        QueryMsg::WithViewingKey { address, key, query, public_key } => {
+            if CONFIG.load(deps.storage)?.reads_paused {
+                return Err(ContractError::ContractPaused)
+            }
+            ViewingKey::check(deps.storage, address.as_str(), &key)?;
+            // The Ethereum address proven by the last permit executed by the user, if any
+            let ethereum_address = ETHEREUM_ADDRESSES.get(deps.storage, &address);
+            let response = query_action(deps, env, address, ethereum_address, query)?;
+            match public_key {
+                Some(public_key) => _encrypt_for_user_public_key(deps, response, public_key),
+                None => Ok(response),
+            }
+        },
+    }
+}
+
+fn query_allowed_origins(deps: Deps) -> StdResult<AllowedOriginsResponse> {
+    let mut sources: Vec<AllowedSource> = Vec::new();
+    for item in ALLOWED_SOURCES.iter(deps.storage)? {
+        let ((source_chain, source_address), _) = item?;
+        sources.push(AllowedSource { source_chain, source_address });
+    }
+
+    let mut relayers: Vec<Addr> = Vec::new();
+    for item in ALLOWED_RELAYERS.iter(deps.storage)? {
+        let (relayer, _) = item?;
+        relayers.push(relayer);
+    }
+
+    Ok(AllowedOriginsResponse { sources, relayers })
+}
+
+fn query_contract_status(deps: Deps) -> StdResult<ContractStatusResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    Ok(ContractStatusResponse {
+        admin: config.admin,
+        writes_paused: config.writes_paused,
+        reads_paused: config.reads_paused,
+    })
+}
+
+fn query_key(deps: Deps) -> StdResult<ContractKeyResponse> {
+    let contract_keys = CONTRACT_KEYS.load(deps.storage)?;
+    Ok(ContractKeyResponse {
+        public_key: contract_keys.public_key,
+        version: contract_keys.version,
+    })
+}
+
+fn permit_queries(
+    deps: Deps, 
+    env: Env, 
+    permit: Permit<DocumentPermission>, 
+    signature_scheme: SignatureScheme, 
+    query: QueryWithPermit
+) -> Result<Binary, ContractError> {
+    
+    // Verify the account through the permit
+    let contract_address = CONFIG.load(deps.storage)?.contract_address;
+    let ethereum_address = _ethereum_address(&permit.signature.pub_key.value)?;
+    let permission = _query_permission(&query);
+
+    let account = _verify_permit(deps, permit.clone(), signature_scheme, contract_address, &env.block.chain_id)?;
+
+    // Verify the permit allows the query
+    _check_permit_permission(&permit, permission)?;
+
+    // Permit validated! We can now execute the query.
+    Ok(query_action(deps, env, account, Some(ethereum_address), query)?)
+}
+
+/// Load the metadata of a file that one of the identities of the user can see.
+fn _load_visible_file(
+    deps: Deps, 
+    block: &BlockInfo, 
+    identities: &Vec<Addr>, 
+    file_id: &String
+) -> StdResult<([u8; 32], FileMetadata)> {
+
+    // Decode the file key
+    let key = match hex::decode(file_id) {
+        Ok(key) => key,
+        _ => return Err(StdError::NotFound { kind: String::from("Invalid key.") })
+    };
+    let u8_key: [u8; 32] = key.try_into()
+        .map_err(|_| StdError::NotFound { kind: String::from("Invalid key.") })?;
+
+    // Check the permission - any role not expired allows to see the file
+    if identities.iter().all(|identity| get_user_file_role(deps.storage, block, u8_key, identity).is_none()) {
+        // Only the former viewers learn that the file was deleted
+        let deleted_file_viewers = DELETED_FILE_VIEWERS.add_suffix(&u8_key);
+        if identities.iter().any(|identity| deleted_file_viewers.contains(deps.storage, identity)) {
+            return Err(StdError::generic_err(ContractError::FileDeleted.to_string()));
+        }
+
+        return Err(StdError::generic_err(format!(
+            "Unauthorized access for the given file."
+        )));
+    };
+
+    let file_metadata_store = ReadonlyPrefixedStorage::new(deps.storage, PREFIX_FILES_METADATA);
+    let file_metadata: FileMetadata = load(&file_metadata_store, &u8_key)?;
+
+    Ok((u8_key, file_metadata))
+}
+
+
+/// Load the metadata of a file whose payload one of the identities of the user can read.
+///
+/// The payload is not served anymore after a revocation with a re-key, until the
+/// owner replaces it.
+fn _load_readable_file(
+    deps: Deps, 
+    block: &BlockInfo, 
+    identities: &Vec<Addr>, 
+    file_id: &String
+) -> StdResult<([u8; 32], FileMetadata)> {
+    let (u8_key, file_metadata) = _load_visible_file(deps, block, identities, file_id)?;
+
+    if file_metadata.pending_rekey {
+        return Err(StdError::generic_err(ContractError::FilePendingRekey.to_string()));
+    }
+
+    Ok((u8_key, file_metadata))
+}
+
+
+/// Answer an authenticated query for the given account.
+/// When provided, the rights given to the Ethereum address of the user apply as well.
+fn query_action(
+    deps: Deps, 
+    env: Env, 
+    account: Addr, 
+    ethereum_address: Option<Addr>, 
+    query: QueryWithPermit
+) -> Result<Binary, StdError> {
+
+    let identities: Vec<Addr> = std::iter::once(account.clone())
+        .chain(ethereum_address)
+        .collect();
+
+    match query {
+        QueryWithPermit::GetFileIds {} => {
+            // Get user file
+            let mut response = FileIdsResponse { file_ids: vec![] };
+            for identity in identities.iter() {
+                for file_id in query_file_ids(deps, &env.block, identity.clone())?.file_ids {
+                    if !response.file_ids.contains(&file_id) {
+                        response.file_ids.push(file_id);
+                    }
+                }
+            }
+
+            to_binary(&response)
+        },
+        QueryWithPermit::GetFileContent { file_id } => {
+            let (u8_key, file_metadata) = _load_readable_file(deps, &env.block, &identities, &file_id)?;
+
+            // Load the latest version of the file and the key wrapped for the user
+            let files_store = ReadonlyPrefixedStorage::new(deps.storage, PREFIX_FILES);
+            let file_state: FileState = load(&files_store, &u8_key)?;
+            let wrapped_key = identities.iter()
+                .find_map(|identity| FILE_KEYS.add_suffix(&u8_key).get(deps.storage, identity));
+            let response = FilePayloadResponse {
+                payload: file_state.payload,
+                wrapped_key,
+                rotated_at: file_metadata.rotated_at,
+                version: file_state.version,
+            };
+
+            to_binary(&response)
+        },
+        QueryWithPermit::GetFileMetadata { file_id } => {
+            // The metadata is visible even while the file is pending re-key
+            let (u8_key, file_metadata) = _load_visible_file(deps, &env.block, &identities, &file_id)?;
+
+            let files_store = ReadonlyPrefixedStorage::new(deps.storage, PREFIX_FILES);
+            let file_state: FileState = load(&files_store, &u8_key)?;
+
+            to_binary(&FileMetadataResponse {
+                owner: file_metadata.owner,
+                info: file_metadata.info,
+                created_at: file_metadata.created_at,
+                updated_at: file_metadata.updated_at,
+                version: file_state.version,
+                pending_rekey: file_metadata.pending_rekey,
+            })
+        },
+        QueryWithPermit::GetFileVersions { file_id } => {
+            let (u8_key, file_metadata) = _load_readable_file(deps, &env.block, &identities, &file_id)?;
+
+            let mut versions: Vec<FileVersionInfo> = Vec::new();
+            for (version, file_version) in _load_file_versions(deps.storage, u8_key, &file_metadata)? {
+                versions.push(FileVersionInfo {
+                    version,
+                    author: file_version.author,
+                    created_at: file_version.created_at,
+                });
+            }
+
+            to_binary(&FileVersionsResponse { versions })
+        },
+        QueryWithPermit::GetFileVersion { file_id, version } => {
+            let (u8_key, file_metadata) = _load_readable_file(deps, &env.block, &identities, &file_id)?;
+
+            let file_version = match _load_file_versions(deps.storage, u8_key, &file_metadata)?
+                .into_iter()
+                .find(|(file_version, _)| *file_version == version) 
+            {
+                Some((_, file_version)) => file_version,
+                None => return Err(StdError::NotFound { kind: String::from("Invalid file version.") })
+            };
+
+            to_binary(&FileVersionResponse {
+                version,
+                author: file_version.author,
+                created_at: file_version.created_at,
+                payload: file_version.payload,
+            })
+        },
+        QueryWithPermit::GetFileAccess { file_id } => {
+            let (u8_key, loaded_metadata) = _load_visible_file(deps, &env.block, &identities, &file_id)?;
+
+            // Check the input user is the owner or a manager
+            let role = identities.iter()
+                .map(|identity| get_file_role(deps.storage, &env.block, u8_key, identity))
+                .max()
+                .flatten();
+            if role < Some(FileRole::Manager) {
+                return Err(StdError::generic_err(format!(
+                    "Unauthorized access for the given file."
+                )));
+            };
+
+            // Expired accesses are listed as well, with their expiration
+            let roles: Vec<RoleAssignment> = loaded_metadata.viewers.iter().filter_map(|viewer| {
+                FILE_PERMISSIONS.get(deps.storage, &(u8_key, viewer.clone())).map(|permission| RoleAssignment {
+                    address: viewer.clone(),
+                    role: permission.role,
+                    expires_at: permission.expires_at,
+                })
+            }).collect();
+
+            let file_access_response = FileAccessResponse {
+                owner: loaded_metadata.owner,
+                viewers: loaded_metadata.viewers,
+                roles,
+                groups: loaded_metadata.groups.iter().map(|group_key| hex::encode(group_key)).collect(),
+                pending_owner: PENDING_OWNERS.get(deps.storage, &u8_key),
+                pending_rekey: loaded_metadata.pending_rekey,
+            };
+
+            to_binary(&file_access_response)
+        },
+        QueryWithPermit::GetAccessRequests {} => {
+            let mut response = AccessRequestsResponse { requests: vec![] };
+            for identity in identities.iter() {
+                response.requests.extend(query_access_requests(deps, &env.block, identity.clone())?.requests);
+            }
+
+            to_binary(&response)
+        },
+        QueryWithPermit::GetAccessRequestStatus { file_id } => {
+
+            // Extract the key
+            let key = match hex::decode(&file_id) {
+                Ok(key) => key,
+                _ => return Err(StdError::NotFound { kind: String::from("Invalid key.") })
+            };
+            let u8_key: [u8; 32] = match key.try_into() {
+                Ok(key) => key,
+                _ => return Err(StdError::NotFound { kind: String::from("Invalid key.") })
+            };
+
+            let access_request = match ACCESS_REQUESTS.add_suffix(&u8_key).get(deps.storage, &account) {
+                Some(access_request) => access_request,
+                None => return Err(StdError::NotFound { kind: String::from("No access request for the given file.") })
+            };
+
+            to_binary(&AccessRequestResponse {
+                file_id,
+                requester: account,
+                note: access_request.note,
+                status: access_request.status,
+                requested_at: access_request.requested_at,
+            })
+        },
+        QueryWithPermit::GetGroupIds {} => {
+            let group_ids: Vec<String> = identities.iter()
+                .flat_map(|identity| USER_GROUPS.get(deps.storage, identity).unwrap_or_default())
+                .map(|group_key| hex::encode(group_key))
+                .collect();
+
+            to_binary(&GroupIdsResponse { group_ids })
+        },
+        QueryWithPermit::GetGroup { group_id } => {
+
+            // Extract the key
+            let key = match hex::decode(&group_id) {
+                Ok(key) => key,
+                _ => return Err(StdError::NotFound { kind: String::from("Invalid key.") })
+            };
+            let u8_key: [u8; 32] = match key.try_into() {
+                Ok(key) => key,
+                _ => return Err(StdError::NotFound { kind: String::from("Invalid key.") })
+            };
+
+            let group = match GROUPS.get(deps.storage, &u8_key) {
+                Some(group) => group,
+                None => return Err(StdError::NotFound { kind: String::from("Invalid group id.") })
+            };
+
+            // Only the owner and the members can see the group
+            if group.owner != account 
+                && !identities.iter().any(|identity| group.members.contains(identity)) 
+            {
+                return Err(StdError::generic_err(format!(
+                    "Unauthorized access for the given group."
+                )));
+            }
+
+            to_binary(&GroupResponse {
+                name: group.name,
+                owner: group.owner,
+                members: group.members,
+                file_ids: group.files.iter().map(|file_key| hex::encode(file_key)).collect(),
+            })
+        },
+        QueryWithPermit::GetFolderIds {} => {
+            let folder_ids: Vec<String> = identities.iter()
+                .flat_map(|identity| USER_FOLDERS.get(deps.storage, identity).unwrap_or_default())
+                .map(|folder_key| hex::encode(folder_key))
+                .collect();
+
+            to_binary(&FolderIdsResponse { folder_ids })
+        },
+        QueryWithPermit::GetFolder { folder_id } => {
+
+            // Extract the key
+            let key = match hex::decode(&folder_id) {
+                Ok(key) => key,
+                _ => return Err(StdError::NotFound { kind: String::from("Invalid key.") })
+            };
+            let u8_key: [u8; 32] = match key.try_into() {
+                Ok(key) => key,
+                _ => return Err(StdError::NotFound { kind: String::from("Invalid key.") })
+            };
+
+            let folder = match FOLDERS.get(deps.storage, &u8_key) {
+                Some(folder) => folder,
+                None => return Err(StdError::NotFound { kind: String::from("Invalid folder id.") })
+            };
+
+            // The access can be given by a parent folder
+            if !identities.iter().any(|identity| has_folder_access(deps.storage, u8_key, identity)) {
+                return Err(StdError::generic_err(format!(
+                    "Unauthorized access for the given folder."
+                )));
+            }
+
+            to_binary(&FolderResponse {
+                name: folder.name,
+                owner: folder.owner,
+                viewers: folder.viewers,
+                parent: folder.parent.map(|parent_key| hex::encode(parent_key)),
+                file_ids: folder.files.iter().map(|file_key| hex::encode(file_key)).collect(),
+                folder_ids: folder.folders.iter().map(|folder_key| hex::encode(folder_key)).collect(),
+            })
+        },
+        QueryWithPermit::GetRevokedPermits {} => {
+            to_binary(&RevokedPermitsResponse {
+                permit_names: USER_REVOKED_PERMITS.get(deps.storage, &account).unwrap_or_default()
+            })
+        }
+    }
+}
+
+/// Return the file ids given a user.
+/// We need to verify with a permit that only the given account is the one that can retrieve the data.
+/// Files for which the user access has expired are not listed.
+fn query_file_ids(deps: Deps, block: &BlockInfo, account: Addr) -> StdResult<FileIdsResponse> {
+
+    // Get user storage
+    let users_store = ReadonlyPrefixedStorage::new(deps.storage, PREFIX_USERS);
+    let loaded_payload: StdResult<Option<UserInfo>> = may_load(&users_store, account.as_bytes());
+
+    let mut file_ids: Vec<String> = match loaded_payload {
+        Ok(Some(user_info)) => {
+            user_info.files.iter()
+                .filter(|&&bytes_key| get_file_role(deps.storage, block, bytes_key, &account).is_some())
+                .map(|&bytes_key| {
+                    hex::encode(&bytes_key)
+                }).collect()
+        }
+        Ok(None) => Vec::new(),
+        Err(error) => return Err(StdError::generic_err(format!(
+            "Error when loading file from storage: {:?}", error
+        )))
+    };
+
+    // Add the files shared with the groups of the user
+    for group_key in USER_GROUPS.get(deps.storage, &account).unwrap_or_default() {
+        if let Some(group) = GROUPS.get(deps.storage, &group_key) {
+            for file_key in group.files {
+                let file_id = hex::encode(&file_key);
+                if !file_ids.contains(&file_id) {
+                    file_ids.push(file_id);
+                }
+            }
+        }
+    }
+
+    Ok(FileIdsResponse { file_ids })
+}
+
+
+/// Return the pending access requests for the files a user manages.
+fn query_access_requests(deps: Deps, block: &BlockInfo, account: Addr) -> StdResult<AccessRequestsResponse> {
+
+    // Get user storage
+    let users_store = ReadonlyPrefixedStorage::new(deps.storage, PREFIX_USERS);
+    let loaded_payload: Option<UserInfo> = may_load(&users_store, account.as_bytes())?;
+
+    let files = match loaded_payload {
+        Some(user_info) => user_info.files,
+        None => Vec::new(),
+    };
+
+    let mut requests: Vec<AccessRequestResponse> = Vec::new();
+
+    for file_key in files {
+
+        // Only the owner and the managers can see the requests
+        if get_file_role(deps.storage, block, file_key, &account) < Some(FileRole::Manager) {
+            continue;
+        }
+
+        // Only the pending requests are indexed, the answered ones are not walked through
+        let file_requests = ACCESS_REQUESTS.add_suffix(&file_key);
+        for item in PENDING_ACCESS_REQUESTS.add_suffix(&file_key).iter_keys(deps.storage)? {
+            let requester = item?;
+
+            if let Some(access_request) = file_requests.get(deps.storage, &requester) {
+                requests.push(AccessRequestResponse {
+                    file_id: hex::encode(file_key),
+                    requester,
+                    note: access_request.note,
+                    status: access_request.status,
+                    requested_at: access_request.requested_at,
+                });
+            }
+        }
+    }
+
+    Ok(AccessRequestsResponse { requests })
+}
+
+
+#[cfg(test)]
+mod tests {
+    
+    use super::*;
+
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+    use cosmwasm_std::{coins, from_binary};
+    use secret_toolkit::permit::{PermitParams, PermitSignature, PubKey};
+    use secret_toolkit::serialization::Serde;
+
+    use crate::state::{LegacyConfig, LegacyContractKeys};
+
+    /// Instanciate a new smart contract
+    fn setup_contract(mut deps: DepsMut) {
+        // Instanciate our Secret Contract
+        let msg = InstantiateMsg { admin: None };
+        let info = mock_info("creator", &coins(0, ""));
+        let response = instantiate(deps.branch(), mock_env(), info, msg).unwrap();
+        assert_eq!(0, response.messages.len());
+
+        // Allow the messages relayed by "anyone" from our Polygon contract
+        let admin_info = mock_info("creator", &coins(0, ""));
+        let msg = ExecuteMsg::UpdateAllowedSources {
+            add: Vec::from([AllowedSource {
+                source_chain: String::from("polygon"),
+                source_address: String::from("0x329CdCBBD82c934fe32322b423bD8fBd30b4EEB6"),
+            }]),
+            remove: Vec::new(),
+        };
+        execute(deps.branch(), mock_env(), admin_info.clone(), msg).unwrap();
+
+        let msg = ExecuteMsg::UpdateAllowedRelayers {
+            add: Vec::from([Addr::unchecked("anyone")]),
+            remove: Vec::new(),
+        };
+        execute(deps, mock_env(), admin_info, msg).unwrap();
+    }
+
+    /// Generate a valid address and a valid permit
+    fn generate_user_1(deps: DepsMut) -> (Addr, Permit<DocumentPermission>) {
+
+        // cosmos2contract
+        let token_address = CONFIG.load(deps.storage).unwrap().contract_address;
+
+        let user_address = "secret1f0pcrxqsgm3ss598nreq3lryv45xa8w7cq55df";
+        let permit_name = "SECRET_DOCUMENT_PERMIT_3812";
+        let chain_id = "secret-4";
+        let pub_key = "A1kUPltujGDLo0vr5M/26mdQF+9cnx8MROF/bL772HDc";
+        let signature = "xt42IhS4k4sb2P1ZNIK6zDWfgaNGB/p9SnHyY9OVNXw1ioGmtMkSVQ/3iBR9gfdFf4K98vyCazg+95n3UrWSNA==";
+
+        let user_permit = Permit {
+            params: PermitParams {
+                allowed_tokens: vec![String::from(token_address)],
+                permit_name: permit_name.to_string(),
+                chain_id: chain_id.to_string(),
+                permissions: vec![DocumentPermission::Owner],
+            },
+            signature: PermitSignature {
+                pub_key: PubKey {
+                    r#type: "tendermint/PubKeySecp256k1".to_string(),
+                    value: Binary::from_base64(pub_key).unwrap(),
+                },
+                signature: Binary::from_base64(signature).unwrap(),
+            }
+        };
+
+        let user_address = deps.api.addr_validate(user_address).unwrap();
+
+        return (user_address, user_permit)
+    }
+
+    fn generate_user_2(deps: DepsMut) -> (Addr, Permit<DocumentPermission>) {
+        // https://permits.scrtlabs.com/
+
+        let token_address = CONFIG.load(deps.storage).unwrap().contract_address;
+
+        let user_address = "secret1u69n2nxq3495t8lw2usn0qgg685ad7d9j0w6lq";
+        let permit_name = "SECRET_DOCUMENT_PERMIT_7616";
+        let chain_id = "secret-4";
+        let pub_key = "A7bPvqlFfe3BccQzfpbRm12RJGnvEy3K+SUDviYs5qWz";
+        let signature = "Znn1BdQreny83DX+sy9JjZMxeU8LtMlnBkt4YKp/FOkZvu6afB5wm+H/d8sThA/DdBkUBbTPk2bD6UgPsuifUA==";
+
+
+        let user_permit = Permit {
+            params: PermitParams {
+                allowed_tokens: vec![String::from(token_address)],
+                permit_name: permit_name.to_string(),
+                chain_id: chain_id.to_string(),
+                permissions: vec![DocumentPermission::Owner],
+            },
+            signature: PermitSignature {
+                pub_key: PubKey {
+                    r#type: "tendermint/PubKeySecp256k1".to_string(),
+                    value: Binary::from_base64(pub_key).unwrap(),
+                },
+                signature: Binary::from_base64(signature).unwrap(),
+            }
+        };
+
+        let user_address = deps.api.addr_validate(user_address).unwrap();
+
+        return (user_address, user_permit)
+    }
+
+
+    /// Generate a valid address and a valid permit signed with Keplr (amino)
+    fn generate_user_3(deps: DepsMut) -> (Addr, Permit<DocumentPermission>) {
+
+        let token_address = CONFIG.load(deps.storage).unwrap().contract_address;
+
+        let user_address = "secret19qxjxm838tyez846ezm25eel7qc0dt08vnflze";
+        let permit_name = "SECRET_DOCUMENT_PERMIT_KEPLR";
+        let chain_id = "secret-4";
+        let pub_key = "A5wJUF2WJTOjMYVnpxrO5gz2Mj6NLqlccDDoDP3jEnTR";
+        let signature = "Cac4ZU7JSpm/JQfKAkvRalaNt4j5PSoS5sIPKOggI2AQgD02YVup3W8UFRX/rigV1U8zCeBVjmkIOAyiAipqRA==";
+
+        let user_permit = Permit {
+            params: PermitParams {
+                allowed_tokens: vec![String::from(token_address)],
+                permit_name: permit_name.to_string(),
+                chain_id: chain_id.to_string(),
+                permissions: vec![DocumentPermission::Owner],
+            },
+            signature: PermitSignature {
+                pub_key: PubKey {
+                    r#type: "tendermint/PubKeySecp256k1".to_string(),
+                    value: Binary::from_base64(pub_key).unwrap(),
+                },
+                signature: Binary::from_base64(signature).unwrap(),
+            }
+        };
+
+        let user_address = deps.api.addr_validate(user_address).unwrap();
+
+        return (user_address, user_permit)
+    }
+
+    /// Generate a valid permit of user 3, only allowing to list (Keplr amino signature)
+    fn generate_user_3_list_permit(deps: DepsMut) -> Permit<DocumentPermission> {
+
+        let token_address = CONFIG.load(deps.storage).unwrap().contract_address;
+
+        let permit_name = "SECRET_DOCUMENT_PERMIT_KEPLR_LIST";
+        let chain_id = "secret-4";
+        let pub_key = "A5wJUF2WJTOjMYVnpxrO5gz2Mj6NLqlccDDoDP3jEnTR";
+        let signature = "35SJbda/X22ISq579oAW6HUkx8S4eqQfhkzZdkFMgqlUQWdTfg6A9zxgE4WxhZnTc0a0C4knQvIQURFyAtrwbw==";
+
+        Permit {
+            params: PermitParams {
+                allowed_tokens: vec![String::from(token_address)],
+                permit_name: permit_name.to_string(),
+                chain_id: chain_id.to_string(),
+                permissions: vec![DocumentPermission::List],
+            },
+            signature: PermitSignature {
+                pub_key: PubKey {
+                    r#type: "tendermint/PubKeySecp256k1".to_string(),
+                    value: Binary::from_base64(pub_key).unwrap(),
+                },
+                signature: Binary::from_base64(signature).unwrap(),
+            }
+        }
+    }
+
+    fn _query_contract_pubic_key(deps: Deps) -> ContractKeyResponse {
+        let query_msg = QueryMsg::GetContractKey {};
+        let response = query(deps, mock_env(), query_msg).unwrap();
+        let key_response: ContractKeyResponse = from_binary(&response).unwrap();
+        key_response
+    }
+
+    fn _generate_local_public_private_key(env: Env) -> (Vec<u8>, Vec<u8>) {
+        // Generate public/private key locally
+        let rng = env.block.random.unwrap().0;
+        let secp = Secp256k1::new();
+
+        let private_key = SecretKey::from_slice(&rng).unwrap();
+        let private_key_string = private_key.display_secret().to_string();
+        let private_key_bytes = hex::decode(private_key_string).unwrap();
+
+        let public_key = PublicKey::from_secret_key(&secp, &private_key);
+        let public_key_bytes = public_key.serialize().to_vec();
+
+        return (public_key_bytes, private_key_bytes);
+    }
+
+    fn _encrypt_with_share_secret(
+        local_private_key: Vec<u8>, 
+        contract_public_key: Vec<u8>, 
+        message_to_encrypt: &Vec<u8>
+    ) -> Vec<u8> {
+        let my_private_key = SecretKey::from_slice(&local_private_key)
+            .map_err(|e| ContractError::CustomError {
+                val: format!("Invalid private key: {}", e),
+            })
+            .unwrap();
+
+        let other_public_key = PublicKey::from_slice(contract_public_key.as_slice())
+            .map_err(|e| ContractError::CustomError {
+                val: format!("Invalid public key: {}", e),
+            })
+            .unwrap();
+
+        let shared_secret = SharedSecret::new(&other_public_key, &my_private_key);
+        let key = shared_secret.secret_bytes();
+
+        // Encrypt our payload
+        let ad_data: &[&[u8]] = &[];
+        let ad = Some(ad_data);
+        let ad = ad.unwrap_or(&[&[]]);
+
+        let mut cipher = Aes128Siv::new(GenericArray::clone_from_slice(&key));
+        let encrypt_message = cipher
+            .encrypt(ad, message_to_encrypt)
+            .map_err(|_e| ContractError::EncryptionError)
+            .unwrap();
+
+        return encrypt_message;
+    }
+
+    fn _decrypt_with_share_secret(
+        local_private_key: Vec<u8>, 
+        contract_public_key: Vec<u8>, 
+        message_to_decrypt: &Vec<u8>
+    ) -> Vec<u8> {
+        let my_private_key = SecretKey::from_slice(&local_private_key).unwrap();
+        let other_public_key = PublicKey::from_slice(contract_public_key.as_slice()).unwrap();
+
+        let shared_secret = SharedSecret::new(&other_public_key, &my_private_key);
+        let key = shared_secret.secret_bytes();
+
+        aes_siv_decrypt(message_to_decrypt, None, &key).unwrap()
+    }
+
+    /// Create an execute message given an action and a user permit
+    fn _create_action_evm_message(
+        deps: Deps,
+        permit: &Permit<DocumentPermission>,
+        action: ExecuteMsgAction
+    ) -> ExecuteMsg {
+        _create_action_evm_message_with_deadline(deps, permit, action, None)
+    }
+
+    /// Create an execute message given an action, a user permit and a deadline
+    fn _create_action_evm_message_with_deadline(
+        deps: Deps,
+        permit: &Permit<DocumentPermission>,
+        action: ExecuteMsgAction,
+        deadline: Option<Expiration>
+    ) -> ExecuteMsg {
+        _create_permit_evm_message(
+            deps,
+            &ExecutePermitMsg::WithPermit { 
+                permit: permit.clone(), 
+                signature_scheme: SignatureScheme::Metamask,
+                execute: action,
+                deadline
+            }
+        )
+    }
+
+    /// Create an execute message given a permit message
+    fn _create_permit_evm_message(
+        deps: Deps,
+        permit_message: &ExecutePermitMsg
+    ) -> ExecuteMsg {
+
+        // Create the message for the action
+        let message = &Json::serialize(permit_message).unwrap();
+
+        // Query the contract public key
+        let contract_key = _query_contract_pubic_key(deps);
+        let contract_public_key = contract_key.public_key;
+
+        // Generate public/private key locally
+        let (local_public_key, local_private_key) = _generate_local_public_private_key(mock_env());
+
+        // Create share secret
+        let encrypted_message = _encrypt_with_share_secret(
+            local_private_key, 
+            contract_public_key, 
+            message
+        );
+
+        // Create the request
+        ExecuteMsg::ReceiveMessageCosmos {
+            source_chain: String::from("polygon"),
+            source_address: String::from("0x329CdCBBD82c934fe32322b423bD8fBd30b4EEB6"),
+            payload: EncryptedExecuteMsg {
+                payload: encrypted_message,
+                public_key: local_public_key,
+                key_version: contract_key.version,
+            },
+        }
+    }
+
+    /// Create an execute message given a file and a user permit
+    fn _create_evm_message(
+        deps: Deps,
+        file: &String, 
+        permit: &Permit<DocumentPermission>
+    ) -> ExecuteMsg {
+        _create_action_evm_message(
+            deps,
+            permit,
+            ExecuteMsgAction::StoreNewFile { 
+                payload: file.clone(),
+                info: None
+            }
+        )
+    }
+
+
+    fn _create_manage_request_evm_message(
+        deps: Deps,
+        permit: &Permit<DocumentPermission>,
+        file_id: String,
+        add_viewing: Vec<Addr>,
+        delete_viewing: Vec<Addr>,
+        change_owner: Addr
+    ) -> ExecuteMsg {
+        _create_action_evm_message(
+            deps,
+            permit,
+            ExecuteMsgAction::ManageFileRights {
+                file_id,
+                add_viewing,
+                delete_viewing,
+                change_owner: Some(change_owner),
+                roles: Vec::new(),
+                add_groups: Vec::new(),
+                delete_groups: Vec::new(),
+                rekey: false
+            }
+        )
+    }
+
+
+
+    fn _query_user_files(deps: Deps, permit: &Permit<DocumentPermission>) -> Vec<String> {
+        let query_msg = QueryMsg::WithPermit { 
+            permit: permit.clone(),
+            signature_scheme: SignatureScheme::Metamask,
+            query: QueryWithPermit::GetFileIds {},
+            public_key: None
+        };
+        let res = query(deps, mock_env(), query_msg);
+        assert!(res.is_ok());
+        
+        let file_id_response = Json::deserialize::<FileIdsResponse>(&res.unwrap()).map(Some);
+
+        // We should now have one key
+        file_id_response.unwrap().unwrap().file_ids
+    }
+
+    fn _query_file(deps: Deps, permit: Permit<DocumentPermission>, file_key: &String) -> String {
+        let query_msg = QueryMsg::WithPermit { 
+            permit,
+            signature_scheme: SignatureScheme::Metamask,
+            query: QueryWithPermit::GetFileContent { file_id: file_key.clone() },
+            public_key: None
+        };
+
+        let response = query(deps, mock_env(), query_msg).unwrap();
+        let file_content: FilePayloadResponse = from_binary(&response).unwrap();
+        file_content.payload
+    }
+
+    fn _query_file_metadata(deps: Deps, permit: Permit<DocumentPermission>, file_key: &String) -> FileAccessResponse {
+        let query_msg = QueryMsg::WithPermit { 
+            permit,
+            signature_scheme: SignatureScheme::Metamask,
+            query: QueryWithPermit::GetFileAccess { file_id: file_key.clone() },
+            public_key: None
+        };
+
+        let response = query(deps, mock_env(), query_msg).unwrap();
+        let file_content: FileAccessResponse = from_binary(&response).unwrap();
+        file_content
+    }
+
+
+    #[test]
+    fn test_contract_initialization() {
+        // Initialize the smart contract
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        // Check that the contract generate a public key
+        let key_response = _query_contract_pubic_key(deps.as_ref());
+        assert_eq!(33, key_response.public_key.len()); // We have an additional 1 byte prefix for the X-coordinate
+    }
+
+
+    #[test]
+    fn keys_initialization() {
+        // Initialize the smart contract
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        
+        let key_response = _query_contract_pubic_key(deps.as_ref());
+        let public_key = key_response.public_key; 
+
+        // Verify that the public key is the same as the one we store
+        let contract_keys = CONTRACT_KEYS.load(deps.as_mut().storage);
+        let storage_public_key = match contract_keys {
+            Ok(keys) => keys.public_key,
+            Err(error) => panic!("Error when loading key from storage: {:?}", error),
+        };
+        assert!(public_key == storage_public_key);
+    }
+
+    #[test]
+    fn test_evm_store_new_file() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        // Generate user information & payload
+        let (_owner, user_permit) = generate_user_1(deps.as_mut());
+        let payload = String::from("{\"file\": \"content\"}");
+
+        let evm_message = _create_evm_message(deps.as_ref(), &payload, &user_permit);
+
+        // Send the evm message
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res_store_file = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res_store_file.is_ok());
+        
+        // Query the user file
+        let user_file = _query_user_files(deps.as_ref(), &user_permit);
+        
+        // We should have the file we previously stored
+        assert_eq!(user_file.len(), 1); 
+
+        // Query with the user the file
+        let file_content = _query_file(deps.as_ref(), user_permit, &user_file[0]);
+
+        // Verify that the store data is the same as the input one
+        assert_eq!(file_content, payload);
+    }
+ 
+
+    #[test]
+    fn test_evm_store_two_same_files() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        // Generate user information & payload
+        let (_owner, user_permit) = generate_user_1(deps.as_mut());
+        let payload = String::from("{\"file\": \"content\"}");
+
+        let evm_message = _create_evm_message(deps.as_ref(), &payload, &user_permit);
+
+        // Send the evm message
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res_store_file = execute(deps.as_mut(), mock_env(), unauth_env.clone(), evm_message.clone());
+        assert!(res_store_file.is_ok());
+        
+        // The same transaction cannot be sent again
+        let res_store_file = execute(deps.as_mut(), mock_env(), unauth_env.clone(), evm_message.clone());
+        assert_eq!(res_store_file.unwrap_err(), ContractError::MessageAlreadyProcessed);
+
+        // Send a new transaction with the same file
+        let evm_message = _create_action_evm_message_with_deadline(
+            deps.as_ref(), 
+            &user_permit, 
+            ExecuteMsgAction::StoreNewFile { payload: payload.clone(), info: None }, 
+            Some(Expiration::AtHeight(mock_env().block.height + 10))
+        );
+        let res_store_file = execute(deps.as_mut(), mock_env(), unauth_env.clone(), evm_message.clone());
+        assert!(res_store_file.is_ok());
+
+        // Query the user file
+        let user_file = _query_user_files(deps.as_ref(), &user_permit);
+        
+        // We should have the files and two different ids
+        assert_eq!(user_file.len(), 2);
+        assert_ne!(user_file[0], user_file[1]);
+
+        // Query with the user the file
+        let file_content_1 = _query_file(deps.as_ref(), user_permit.clone(), &user_file[0]);
+        let file_content_2 = _query_file(deps.as_ref(), user_permit.clone(), &user_file[1]);
+
+        // Verify that the store data is the same as the input one
+        assert_eq!(file_content_1, payload);
+        assert_eq!(file_content_2, payload);
+        assert_eq!(file_content_1, file_content_2);
+    }
+
+
+
+    #[test]
+    fn test_retrieve_file_from_invalid_key() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        let (_owner, user_permit) = generate_user_1(deps.as_mut());
+        
+        // Query with the user the file
+        let query_msg = QueryMsg::WithPermit { 
+            permit: user_permit,
+            signature_scheme: SignatureScheme::Metamask,
+            query: QueryWithPermit::GetFileContent { file_id: String::from("invalid_key") },
+            public_key: None
+        };
+
+        let response = query(deps.as_ref(), mock_env(), query_msg);
+        assert!(response.is_err());
+    }
+
+
+    #[test]
+    fn test_retrieve_file_with_no_access() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        // Generate user information & payload
+        let (_owner, user_permit) = generate_user_1(deps.as_mut());
+        let payload = String::from("{\"file\": \"content\"}");
+
+        let evm_message = _create_evm_message(deps.as_ref(), &payload, &user_permit);
+
+        // Send the evm message
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res_store_file = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res_store_file.is_ok());
+        
+        // Query the user file
+        let user_file = _query_user_files(deps.as_ref(), &user_permit);
+        
+        // We should have the file we previously stored
+        assert_eq!(user_file.len(), 1); 
+
+        // Generate another user
+        let (_user_2, user_permit_2) = generate_user_2(deps.as_mut());
+
+        // Try to get the file of the user 1
+        let query_msg = QueryMsg::WithPermit { 
+            permit: user_permit_2.clone(),
+            signature_scheme: SignatureScheme::Metamask,
+            query: QueryWithPermit::GetFileContent { file_id: user_file[0].clone() },
+            public_key: None
+        };
+        let response = query(deps.as_ref(), mock_env(), query_msg);
+        assert!(response.is_err());
+    }
+
+
+
+    #[test]
+    fn test_store_file_and_give_user_access() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        // Generate user info
+        let (user_1, user_1_permit) = generate_user_1(deps.as_mut());
+        let (user_2, user_2_permit) = generate_user_2(deps.as_mut());
+
+        // Generate user information & payload
+        let payload = String::from("{\"file\": \"content\"}");
+
+        let evm_message = _create_evm_message(deps.as_ref(), &payload, &user_1_permit);
+
+        // Send the evm message
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res_store_file = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res_store_file.is_ok());
+
+        // User 1 should have one file
+        let user_1_file = _query_user_files(deps.as_ref(), &user_1_permit);
+        assert!(!user_1_file.is_empty());
+
+        // User 2 should not have a file
+        let user_2_file = _query_user_files(deps.as_ref(), &user_2_permit);
+        assert!(user_2_file.is_empty());
+
+        // Create request to authorize user 2
+        let evm_message = _create_manage_request_evm_message(
+            deps.as_ref(),
+            &user_1_permit,
+            user_1_file[0].clone(),
+            Vec::from([user_2.clone()]),
+            Vec::new(),
+            user_1.clone()
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res_store_file = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res_store_file.is_ok());
+
+        // See the list of the user 2
+        let user_2_file = _query_user_files(deps.as_ref(), &user_2_permit);
+        assert_eq!(user_2_file.len(), 1);
+
+        // Get the file from the user 2
+        let file_content = _query_file(deps.as_ref(), user_2_permit, &user_2_file[0]);
+        
+        // Verify that the store data is the same as the input one
+        assert_eq!(file_content, payload);
+
+        // Check user 1 still have access to the file
+        let user_1_file = _query_user_files(deps.as_ref(), &user_1_permit);
+        assert_eq!(user_1_file.len(), 1);
+        let file_content = _query_file(deps.as_ref(), user_1_permit.clone(), &user_1_file[0]);
+        assert_eq!(file_content, payload);
+
+
+        // From the owner, get the file permission
+        let file_metadata = _query_file_metadata(deps.as_ref(), user_1_permit.clone(), &user_1_file[0]);
+        assert_eq!(file_metadata.owner, user_1.clone());
+        assert_eq!(file_metadata.viewers.len(), 2);
+        assert_eq!(file_metadata.viewers[0], user_1.clone());
+        assert_eq!(file_metadata.viewers[1], user_2.clone());
+
+    }    
+    
+
+
+
+    #[test]
+    fn test_unauthorized_user_update_file_rights() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        // Generate user info
+        let (user_1, user_1_permit) = generate_user_1(deps.as_mut());
+        let (user_2, user_2_permit) = generate_user_2(deps.as_mut());
+
+        // Generate user information & payload
+        let payload = String::from("{\"file\": \"content\"}");
+
+        let evm_message = _create_evm_message(deps.as_ref(), &payload, &user_1_permit);
+
+        // Send the evm message
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res_store_file = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res_store_file.is_ok());
+
+        // Get file id
+        let file_id = &_query_user_files(deps.as_ref(), &user_1_permit)[0];
+        
+        // User 2 try to add in viewing
+        let evm_message = _create_manage_request_evm_message(
+            deps.as_ref(),
+            &user_2_permit,
+            file_id.clone(),
+            Vec::from([user_2.clone()]),
+            Vec::new(),
+            user_1.clone()
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res_store_file = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res_store_file.is_err());
+
+
+        // User 2 try to delete user 1
+        let evm_message = _create_manage_request_evm_message(
+            deps.as_ref(),
+            &user_2_permit,
+            file_id.clone(),
+            Vec::new(),
+            Vec::from([user_2.clone()]),
+            user_1.clone()
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res_store_file = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res_store_file.is_err());
+
+        // User 2 try to get the owner
+        let evm_message = _create_manage_request_evm_message(
+            deps.as_ref(),
+            &user_2_permit,
+            file_id.clone(),
+            Vec::new(),
+            Vec::new(),
+            user_2.clone()
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res_store_file = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res_store_file.is_err());
+
+    }
+
+    #[test]
+    fn test_remove_viewing_rights_and_keep_owner() {
+        // Given a file created by an user, we want to check that if we delete the user, who owns the file 
+        // we will have an error. And the owner can still have access to the file.
+        //
+        // Note: As the transaction is in error, the transaction will be reverted. However,
+        //      here, the storage modification is not modify. This is wy in our test, at the
+        //      end we do not verify the user 2 query.
+        
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        // Generate user info
+        let (user_1, user_1_permit) = generate_user_1(deps.as_mut());
+        let (user_2, _user_2_permit) = generate_user_2(deps.as_mut());
+
+        // Generate user information & payload
+        let payload = String::from("{\"file\": \"content\"}");
+
+        let evm_message = _create_evm_message(deps.as_ref(), &payload, &user_1_permit);
+
+        // Send the evm message
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res_store_file = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res_store_file.is_ok());
+
+        // Get file id
+        let file_id = &_query_user_files(deps.as_ref(), &user_1_permit)[0];
+        
+        // User 1 add user 2 access and cannot remove himself of the list as it is still the owner
+        let evm_message = _create_manage_request_evm_message(
+            deps.as_ref(),
+            &user_1_permit,
+            file_id.clone(),
+            Vec::from([user_2.clone()]),
+            Vec::from([user_1.clone()]),
+            user_1.clone()
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res_store_file = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res_store_file.is_err());
+    }
+
+    #[test]
+    fn test_transfert_file_ownership() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        // Generate user info
+        let (user_1, user_1_permit) = generate_user_1(deps.as_mut());
+        let (user_2, user_2_permit) = generate_user_2(deps.as_mut());
+
+        // Generate user information & payload
+        let payload = String::from("{\"file\": \"content\"}");
+
+        let evm_message = _create_evm_message(deps.as_ref(), &payload, &user_1_permit);
+
+        // Send the evm message
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res_store_file = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res_store_file.is_ok());
+
+        // User 1 should have one file
+        let user_1_file = _query_user_files(deps.as_ref(), &user_1_permit);
+        assert!(!user_1_file.is_empty());
+
+        // User 2 should not have a file
+        let user_2_file = _query_user_files(deps.as_ref(), &user_2_permit);
+        assert!(user_2_file.is_empty());
+        
+        // The owner cannot be changed directly
+        let updated_user_id = user_1_file[0].clone();
+        let evm_message = _create_manage_request_evm_message(
+            deps.as_ref(),
+            &user_1_permit,
+            updated_user_id.clone(),
+            Vec::new(),
+            Vec::new(),
+            user_2.clone()
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert_eq!(res.unwrap_err(), ContractError::OwnershipTransferRequired);
+
+        // User 1 proposes the ownership to user 2
+        let evm_message = _create_action_evm_message(
+            deps.as_ref(),
+            &user_1_permit,
+            ExecuteMsgAction::ProposeOwnershipTransfer { 
+                file_id: updated_user_id.clone(), 
+                new_owner: user_2.clone() 
+            }
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res.is_ok());
+
+        // The ownership only moves once accepted
+        let file_metadata = _query_file_metadata(deps.as_ref(), user_1_permit.clone(), &updated_user_id);
+        assert_eq!(file_metadata.owner, user_1.clone());
+        assert_eq!(file_metadata.pending_owner, Some(user_2.clone()));
+
+        // User 1 cannot accept the ownership for user 2
+        let evm_message = _create_action_evm_message(
+            deps.as_ref(),
+            &user_1_permit,
+            ExecuteMsgAction::AcceptOwnership { file_id: updated_user_id.clone() }
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert_eq!(res.unwrap_err(), ContractError::NoPendingOwnershipTransfer);
+
+        // User 2 accepts the ownership
+        let evm_message = _create_action_evm_message(
+            deps.as_ref(),
+            &user_2_permit,
+            ExecuteMsgAction::AcceptOwnership { file_id: updated_user_id.clone() }
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res.is_ok());
+
+        // User 1 should still have access to the file
+        let user_1_file = _query_user_files(deps.as_ref(), &user_1_permit);
+        assert!(!user_1_file.is_empty());
+        assert_eq!(user_1_file[0], updated_user_id);
+
+        // User 2 should now have access to the file
+        let user_2_file = _query_user_files(deps.as_ref(), &user_2_permit);
+        assert!(!user_2_file.is_empty());
+        assert_eq!(user_2_file[0], updated_user_id);
+
+        // User 2 is now the owner
+        let file_metadata = _query_file_metadata(deps.as_ref(), user_2_permit.clone(), &updated_user_id);
+        assert_eq!(file_metadata.owner, user_2.clone());
+        assert_eq!(file_metadata.pending_owner, None);
+    }
+
+
+
+    #[test]
+    fn test_manager_manage_file_rights() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        // Generate user info
+        let (user_1, user_1_permit) = generate_user_1(deps.as_mut());
+        let (user_2, user_2_permit) = generate_user_2(deps.as_mut());
+        let user_3 = Addr::unchecked("secret1ncgrta0phcl5t4707sg0qkn0cd8agr95nytfpy");
+
+        // User 1 store a new file
+        let payload = String::from("{\"file\": \"content\"}");
+        let evm_message = _create_evm_message(deps.as_ref(), &payload, &user_1_permit);
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res_store_file = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res_store_file.is_ok());
+
+        let file_id = _query_user_files(deps.as_ref(), &user_1_permit)[0].clone();
+
+        // User 1 makes user 2 a manager of the file
+        let evm_message = _create_action_evm_message(
+            deps.as_ref(),
+            &user_1_permit,
+            ExecuteMsgAction::ManageFileRights {
+                file_id: file_id.clone(),
+                add_viewing: Vec::new(),
+                delete_viewing: Vec::new(),
+                change_owner: Some(user_1.clone()),
+                roles: Vec::from([RoleAssignment { address: user_2.clone(), role: FileRole::Manager, expires_at: None }]),
+                add_groups: Vec::new(),
+                delete_groups: Vec::new(),
+                rekey: false
+            }
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res.is_ok());
+
+        // User 2 can give a viewing access to user 3
+        let evm_message = _create_manage_request_evm_message(
+            deps.as_ref(),
+            &user_2_permit,
+            file_id.clone(),
+            Vec::from([user_3.clone()]),
+            Vec::new(),
+            user_1.clone()
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res.is_ok());
+
+        // User 2 can see the file access
+        let file_access = _query_file_metadata(deps.as_ref(), user_2_permit.clone(), &file_id);
+        assert_eq!(file_access.owner, user_1.clone());
+        assert_eq!(file_access.roles, Vec::from([
+            RoleAssignment { address: user_1.clone(), role: FileRole::Owner, expires_at: None },
+            RoleAssignment { address: user_2.clone(), role: FileRole::Manager, expires_at: None },
+            RoleAssignment { address: user_3.clone(), role: FileRole::Viewer, expires_at: None },
+        ]));
+
+        // User 2 cannot take the ownership of the file
+        let evm_message = _create_manage_request_evm_message(
+            deps.as_ref(),
+            &user_2_permit,
+            file_id.clone(),
+            Vec::new(),
+            Vec::new(),
+            user_2.clone()
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res.is_err());
+
+        // User 2 cannot remove the owner
+        let evm_message = _create_manage_request_evm_message(
+            deps.as_ref(),
+            &user_2_permit,
+            file_id.clone(),
+            Vec::new(),
+            Vec::from([user_1.clone()]),
+            user_1.clone()
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res.is_err());
+    }
+
+
+    #[test]
+    fn test_file_access_expiration() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        // Generate user info
+        let (user_1, user_1_permit) = generate_user_1(deps.as_mut());
+        let (user_2, user_2_permit) = generate_user_2(deps.as_mut());
+
+        // User 1 store a new file
+        let payload = String::from("{\"file\": \"content\"}");
+        let evm_message = _create_evm_message(deps.as_ref(), &payload, &user_1_permit);
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res_store_file = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res_store_file.is_ok());
+
+        let file_id = _query_user_files(deps.as_ref(), &user_1_permit)[0].clone();
+
+        // User 1 gives user 2 an access for 10 blocks
+        let expires_at = Expiration::AtHeight(mock_env().block.height + 10);
+        let evm_message = _create_action_evm_message(
+            deps.as_ref(),
+            &user_1_permit,
+            ExecuteMsgAction::ManageFileRights {
+                file_id: file_id.clone(),
+                add_viewing: Vec::new(),
+                delete_viewing: Vec::new(),
+                change_owner: Some(user_1.clone()),
+                roles: Vec::from([RoleAssignment { 
+                    address: user_2.clone(), 
+                    role: FileRole::Viewer, 
+                    expires_at: Some(expires_at.clone()) 
+                }]),
+                add_groups: Vec::new(),
+                delete_groups: Vec::new(),
+                rekey: false
+            }
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res.is_ok());
+
+        // The owner can see the expiration
+        let file_access = _query_file_metadata(deps.as_ref(), user_1_permit.clone(), &file_id);
+        assert_eq!(file_access.roles[1].address, user_2.clone());
+        assert_eq!(file_access.roles[1].expires_at, Some(expires_at));
+
+        // User 2 can see the file before the expiration
+        let file_content = _query_file(deps.as_ref(), user_2_permit.clone(), &file_id);
+        assert_eq!(file_content, payload);
+
+        // Once expired, user 2 cannot see the file anymore
+        let mut expired_env = mock_env();
+        expired_env.block.height += 10;
+
+        let query_msg = QueryMsg::WithPermit { 
+            permit: user_2_permit.clone(),
+            signature_scheme: SignatureScheme::Metamask,
+            query: QueryWithPermit::GetFileContent { file_id: file_id.clone() },
+            public_key: None
+        };
+        let response = query(deps.as_ref(), expired_env.clone(), query_msg);
+        assert!(response.is_err());
+
+        let query_msg = QueryMsg::WithPermit { 
+            permit: user_2_permit.clone(),
+            signature_scheme: SignatureScheme::Metamask,
+            query: QueryWithPermit::GetFileIds {},
+            public_key: None
+        };
+        let response = query(deps.as_ref(), expired_env, query_msg).unwrap();
+        let file_ids: FileIdsResponse = from_binary(&response).unwrap();
+        assert!(file_ids.file_ids.is_empty());
+    }
+
+
+    #[test]
+    fn test_share_file_with_group() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        // Generate user info
+        let (user_1, user_1_permit) = generate_user_1(deps.as_mut());
+        let (user_2, user_2_permit) = generate_user_2(deps.as_mut());
+
+        // User 1 store a new file
+        let payload = String::from("{\"file\": \"content\"}");
+        let evm_message = _create_evm_message(deps.as_ref(), &payload, &user_1_permit);
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res_store_file = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res_store_file.is_ok());
+
+        let file_id = _query_user_files(deps.as_ref(), &user_1_permit)[0].clone();
+
+        // User 1 creates a group with user 2
+        let evm_message = _create_action_evm_message(
+            deps.as_ref(),
+            &user_1_permit,
+            ExecuteMsgAction::CreateGroup {
+                name: String::from("Board"),
+                members: Vec::from([user_2.clone()])
+            }
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res.is_ok());
+
+        // User 2 is a member of the group
+        let query_msg = QueryMsg::WithPermit { 
+            permit: user_2_permit.clone(),
+            signature_scheme: SignatureScheme::Metamask,
+            query: QueryWithPermit::GetGroupIds {},
+            public_key: None
+        };
+        let response = query(deps.as_ref(), mock_env(), query_msg).unwrap();
+        let group_ids: GroupIdsResponse = from_binary(&response).unwrap();
+        assert_eq!(group_ids.group_ids.len(), 1);
+        let group_id = group_ids.group_ids[0].clone();
+
+        // User 1 shares the file with the group
+        let evm_message = _create_action_evm_message(
+            deps.as_ref(),
+            &user_1_permit,
+            ExecuteMsgAction::ManageFileRights {
+                file_id: file_id.clone(),
+                add_viewing: Vec::new(),
+                delete_viewing: Vec::new(),
+                change_owner: Some(user_1.clone()),
+                roles: Vec::new(),
+                add_groups: Vec::from([group_id.clone()]),
+                delete_groups: Vec::new(),
+                rekey: false
+            }
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res.is_ok());
+
+        // User 2 can see the file through the group
+        let user_2_file = _query_user_files(deps.as_ref(), &user_2_permit);
+        assert_eq!(user_2_file, Vec::from([file_id.clone()]));
+        let file_content = _query_file(deps.as_ref(), user_2_permit.clone(), &file_id);
+        assert_eq!(file_content, payload);
+
+        // User 1 removes user 2 from the group
+        let evm_message = _create_action_evm_message(
+            deps.as_ref(),
+            &user_1_permit,
+            ExecuteMsgAction::UpdateGroupMembers {
+                group_id: group_id.clone(),
+                add_members: Vec::new(),
+                remove_members: Vec::from([user_2.clone()])
+            }
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res.is_ok());
+
+        // User 2 lost the access to the file
+        let user_2_file = _query_user_files(deps.as_ref(), &user_2_permit);
+        assert!(user_2_file.is_empty());
+
+        let query_msg = QueryMsg::WithPermit { 
+            permit: user_2_permit.clone(),
+            signature_scheme: SignatureScheme::Metamask,
+            query: QueryWithPermit::GetFileContent { file_id: file_id.clone() },
+            public_key: None
+        };
+        let response = query(deps.as_ref(), mock_env(), query_msg);
+        assert!(response.is_err());
+
+        // A user cannot share his files with a group he is not part of
+        let payload = String::from("{\"file\": \"user 2 content\"}");
+        let evm_message = _create_evm_message(deps.as_ref(), &payload, &user_2_permit);
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res.is_ok());
+
+        let user_2_file_id = _query_user_files(deps.as_ref(), &user_2_permit)[0].clone();
+        let share_with_group = ExecuteMsgAction::ManageFileRights {
+            file_id: user_2_file_id.clone(),
+            add_viewing: Vec::new(),
+            delete_viewing: Vec::new(),
+            change_owner: None,
+            roles: Vec::new(),
+            add_groups: Vec::from([group_id.clone()]),
+            delete_groups: Vec::new(),
+            rekey: false
+        };
+        let evm_message = _create_action_evm_message(deps.as_ref(), &user_2_permit, share_with_group.clone());
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert_eq!(res.unwrap_err(), ContractError::UnauthorizedGroupAccess);
+
+        let group = GROUPS.get(deps.as_ref().storage, &hex::decode(&group_id).unwrap().try_into().unwrap()).unwrap();
+        assert_eq!(group.files, Vec::from([hex::decode(&file_id).unwrap().try_into().unwrap()]));
+
+        // A member of the group can
+        let evm_message = _create_action_evm_message(
+            deps.as_ref(),
+            &user_1_permit,
+            ExecuteMsgAction::UpdateGroupMembers {
+                group_id: group_id.clone(),
+                add_members: Vec::from([user_2.clone()]),
+                remove_members: Vec::new()
+            }
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res.is_ok());
+
+        let evm_message = _create_action_evm_message(deps.as_ref(), &user_2_permit, share_with_group);
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res.is_ok());
+
+        // The name and the members of a group are bounded
+        let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+        let msg = ExecuteMsg::Direct { 
+            execute: ExecuteMsgAction::CreateGroup { name: "a".repeat(MAX_GROUP_NAME_LENGTH + 1), members: Vec::new() } 
+        };
+        let res = execute(deps.as_mut(), mock_env(), user_1_env, msg);
+        assert_eq!(res.unwrap_err(), ContractError::CustomError { val: String::from("The name of a group cannot exceed 128 bytes") });
+
+        let members: Vec<Addr> = (0..MAX_GROUP_MEMBERS).map(|index| Addr::unchecked(format!("member_{}", index))).collect();
+        let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+        let msg = ExecuteMsg::Direct { 
+            execute: ExecuteMsgAction::UpdateGroupMembers { 
+                group_id: group_id.clone(), 
+                add_members: members.clone(), 
+                remove_members: Vec::new() 
+            } 
+        };
+        let res = execute(deps.as_mut(), mock_env(), user_1_env, msg);
+        assert_eq!(res.unwrap_err(), ContractError::CustomError { val: String::from("A group cannot have more than 256 members") });
+        assert!(USER_GROUPS.get(deps.as_ref().storage, &members[0]).is_none());
+
+        let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+        let msg = ExecuteMsg::Direct { execute: ExecuteMsgAction::CreateGroup { name: String::from("Everyone"), members } };
+        let res = execute(deps.as_mut(), mock_env(), user_1_env, msg);
+        assert_eq!(res.unwrap_err(), ContractError::CustomError { val: String::from("A group cannot have more than 256 members") });
+    }
+
+
+    #[test]
+    fn test_request_access_to_file() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        // Generate user info
+        let (_user_1, user_1_permit) = generate_user_1(deps.as_mut());
+        let (user_2, user_2_permit) = generate_user_2(deps.as_mut());
+
+        // User 1 store a new file
+        let payload = String::from("{\"file\": \"content\"}");
+        let evm_message = _create_evm_message(deps.as_ref(), &payload, &user_1_permit);
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res_store_file = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res_store_file.is_ok());
+
+        let file_id = _query_user_files(deps.as_ref(), &user_1_permit)[0].clone();
+
+        // User 2 asks for an access
+        let evm_message = _create_action_evm_message(
+            deps.as_ref(),
+            &user_2_permit,
+            ExecuteMsgAction::RequestAccess { 
+                file_id: file_id.clone(), 
+                note: String::from("Board member") 
+            }
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res.is_ok());
+
+        // User 1 sees the pending request
+        let query_msg = QueryMsg::WithPermit { 
+            permit: user_1_permit.clone(),
+            signature_scheme: SignatureScheme::Metamask,
+            query: QueryWithPermit::GetAccessRequests {},
+            public_key: None
+        };
+        let response = query(deps.as_ref(), mock_env(), query_msg).unwrap();
+        let access_requests: AccessRequestsResponse = from_binary(&response).unwrap();
+        assert_eq!(access_requests.requests.len(), 1);
+        assert_eq!(access_requests.requests[0].file_id, file_id.clone());
+        assert_eq!(access_requests.requests[0].requester, user_2.clone());
+        assert_eq!(access_requests.requests[0].note, String::from("Board member"));
+
+        // User 2 cannot approve his own request
+        let evm_message = _create_action_evm_message(
+            deps.as_ref(),
+            &user_2_permit,
+            ExecuteMsgAction::ApproveRequest { file_id: file_id.clone(), requester: user_2.clone() }
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert_eq!(res.unwrap_err(), ContractError::UnauthorizedAccess);
+
+        // User 1 approves the request
+        let evm_message = _create_action_evm_message(
+            deps.as_ref(),
+            &user_1_permit,
+            ExecuteMsgAction::ApproveRequest { file_id: file_id.clone(), requester: user_2.clone() }
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res.is_ok());
+
+        // User 2 sees the status of his request and can see the file
+        let query_msg = QueryMsg::WithPermit { 
+            permit: user_2_permit.clone(),
+            signature_scheme: SignatureScheme::Metamask,
+            query: QueryWithPermit::GetAccessRequestStatus { file_id: file_id.clone() },
+            public_key: None
+        };
+        let response = query(deps.as_ref(), mock_env(), query_msg).unwrap();
+        let access_request: AccessRequestResponse = from_binary(&response).unwrap();
+        assert_eq!(access_request.status, AccessRequestStatus::Approved);
+
+        let file_content = _query_file(deps.as_ref(), user_2_permit.clone(), &file_id);
+        assert_eq!(file_content, payload);
+
+        // No more pending request
+        let query_msg = QueryMsg::WithPermit { 
+            permit: user_1_permit.clone(),
+            signature_scheme: SignatureScheme::Metamask,
+            query: QueryWithPermit::GetAccessRequests {},
+            public_key: None
+        };
+        let response = query(deps.as_ref(), mock_env(), query_msg).unwrap();
+        let access_requests: AccessRequestsResponse = from_binary(&response).unwrap();
+        assert!(access_requests.requests.is_empty());
+
+        // The same error for a user who already has access and for an unknown file
+        let evm_message = _create_action_evm_message(
+            deps.as_ref(),
+            &user_2_permit,
+            ExecuteMsgAction::RequestAccess { file_id: file_id.clone(), note: String::from("Board member, again") }
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert_eq!(res.unwrap_err(), ContractError::InvalidAccessRequest);
+
+        let evm_message = _create_action_evm_message(
+            deps.as_ref(),
+            &user_2_permit,
+            ExecuteMsgAction::RequestAccess { file_id: hex::encode([7u8; 32]), note: String::from("Board member") }
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert_eq!(res.unwrap_err(), ContractError::InvalidAccessRequest);
+
+        // A denied request cannot be renewed
+        let (user_3, _) = generate_user_3(deps.as_mut());
+        let request_msg = |note: &str| ExecuteMsg::Direct { 
+            execute: ExecuteMsgAction::RequestAccess { file_id: file_id.clone(), note: String::from(note) } 
+        };
+        let user_3_env = mock_info(user_3.as_str(), &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), user_3_env, request_msg("Auditor"));
+        assert!(res.is_ok());
+
+        let evm_message = _create_action_evm_message(
+            deps.as_ref(),
+            &user_1_permit,
+            ExecuteMsgAction::DenyRequest { file_id: file_id.clone(), requester: user_3.clone() }
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res.is_ok());
+
+        let user_3_env = mock_info(user_3.as_str(), &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), user_3_env, request_msg("Auditor, please"));
+        assert_eq!(res.unwrap_err(), ContractError::InvalidAccessRequest);
+
+        let file_key: [u8; 32] = hex::decode(&file_id).unwrap().try_into().unwrap();
+        let access_request = ACCESS_REQUESTS.add_suffix(&file_key).get(deps.as_ref().storage, &user_3).unwrap();
+        assert_eq!(access_request.status, AccessRequestStatus::Denied);
+        assert_eq!(access_request.note, String::from("Auditor"));
+
+        // The answered requests are kept for the requesters but no longer listed
+        assert!(PENDING_ACCESS_REQUESTS.add_suffix(&file_key).is_empty(deps.as_ref().storage));
+
+        // The note is bounded
+        let requester_env = mock_info("requester", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), requester_env, request_msg(&"a".repeat(MAX_ACCESS_REQUEST_NOTE_LENGTH + 1)));
+        assert_eq!(res.unwrap_err(), ContractError::CustomError { val: String::from("The note cannot exceed 256 bytes") });
+        assert!(ACCESS_REQUESTS.add_suffix(&file_key).get(deps.as_ref().storage, &Addr::unchecked("requester")).is_none());
+    }
+
+
+    #[test]
+    fn test_claim_file_access() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        // Generate user info
+        let (_user_1, user_1_permit) = generate_user_1(deps.as_mut());
+        let (_user_2, user_2_permit) = generate_user_2(deps.as_mut());
+
+        // User 1 store a new file
+        let payload = String::from("{\"file\": \"content\"}");
+        let evm_message = _create_evm_message(deps.as_ref(), &payload, &user_1_permit);
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res_store_file = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res_store_file.is_ok());
+
+        let file_id = _query_user_files(deps.as_ref(), &user_1_permit)[0].clone();
+
+        // User 1 registers a claim code for a single user
+        let preimage = String::from("board-invitation-2024");
+        let claim_hash = hex::encode(Sha256::digest(preimage.as_bytes()));
+        let evm_message = _create_action_evm_message(
+            deps.as_ref(),
+            &user_1_permit,
+            ExecuteMsgAction::RegisterClaimCode { 
+                file_id: file_id.clone(), 
+                claim_hash,
+                max_claims: Some(1),
+                expires_at: None
+            }
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res.is_ok());
+
+        // A wrong preimage does not give any access
+        let evm_message = _create_action_evm_message(
+            deps.as_ref(),
+            &user_2_permit,
+            ExecuteMsgAction::ClaimAccess { preimage: String::from("wrong-preimage") }
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert_eq!(res.unwrap_err(), ContractError::InvalidClaimCode);
+
+        // User 2 claims the access with the preimage
+        let evm_message = _create_action_evm_message(
+            deps.as_ref(),
+            &user_2_permit,
+            ExecuteMsgAction::ClaimAccess { preimage: preimage.clone() }
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env.clone(), evm_message.clone());
+        assert!(res.is_ok());
+
+        let user_2_file = _query_user_files(deps.as_ref(), &user_2_permit);
+        assert_eq!(user_2_file, Vec::from([file_id.clone()]));
+        let file_content = _query_file(deps.as_ref(), user_2_permit.clone(), &file_id);
+        assert_eq!(file_content, payload);
+
+        // The same message cannot be sent again
+        let res = execute(deps.as_mut(), mock_env(), unauth_env.clone(), evm_message);
+        assert_eq!(res.unwrap_err(), ContractError::MessageAlreadyProcessed);
+
+        // The code cannot be claimed anymore
+        let evm_message = _create_action_evm_message_with_deadline(
+            deps.as_ref(),
+            &user_2_permit,
+            ExecuteMsgAction::ClaimAccess { preimage: preimage.clone() },
+            Some(Expiration::AtHeight(mock_env().block.height + 10))
+        );
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert_eq!(res.unwrap_err(), ContractError::InvalidClaimCode);
+    }
+
+
+    #[test]
+    fn test_share_file_with_ethereum_address() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        // Generate user info
+        let (user_1, user_1_permit) = generate_user_1(deps.as_mut());
+        let (user_2, user_2_permit) = generate_user_2(deps.as_mut());
+
+        // The Ethereum address is derived from the public key of the permit
+        let user_2_ethereum_address = Addr::unchecked("0xfc7a3a16babf17024c25dfd8c081fbbc0d843e68");
+        assert_eq!(
+            _ethereum_address(&user_2_permit.signature.pub_key.value).unwrap(), 
+            user_2_ethereum_address
+        );
+
+        // User 1 store a new file
+        let payload = String::from("{\"file\": \"content\"}");
+        let evm_message = _create_evm_message(deps.as_ref(), &payload, &user_1_permit);
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res_store_file = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res_store_file.is_ok());
+
+        let file_id = _query_user_files(deps.as_ref(), &user_1_permit)[0].clone();
+
+        // User 1 share the file with the Ethereum address of user 2 (checksummed)
+        let evm_message = _create_manage_request_evm_message(
+            deps.as_ref(),
+            &user_1_permit,
+            file_id.clone(),
+            Vec::from([Addr::unchecked("0xFC7a3A16BabF17024c25dfD8c081fbBC0d843E68")]),
+            Vec::new(),
+            user_1.clone()
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res.is_ok());
+
+        let file_metadata = _query_file_metadata(deps.as_ref(), user_1_permit.clone(), &file_id);
+        assert_eq!(file_metadata.viewers, Vec::from([user_1.clone(), user_2_ethereum_address.clone()]));
+
+        // User 2 can see the file with his permit
+        let user_2_file = _query_user_files(deps.as_ref(), &user_2_permit);
+        assert_eq!(user_2_file, Vec::from([file_id.clone()]));
+        let file_content = _query_file(deps.as_ref(), user_2_permit.clone(), &file_id);
+        assert_eq!(file_content, payload);
+
+        // Once user 2 sends a message, the access is attached to his secret address
+        let other_payload = String::from("{\"file\": \"other content\"}");
+        let evm_message = _create_evm_message(deps.as_ref(), &other_payload, &user_2_permit);
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res.is_ok());
+
+        let file_metadata = _query_file_metadata(deps.as_ref(), user_1_permit.clone(), &file_id);
+        assert_eq!(file_metadata.viewers, Vec::from([user_1.clone(), user_2.clone()]));
+
+        let user_2_file = _query_user_files(deps.as_ref(), &user_2_permit);
+        assert_eq!(user_2_file.len(), 2);
+        assert!(user_2_file.contains(&file_id));
+        let file_content = _query_file(deps.as_ref(), user_2_permit.clone(), &file_id);
+        assert_eq!(file_content, payload);
+    }
+
+
+    #[test]
+    fn test_folder_inherited_access() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        // Generate user info
+        let (_user_1, user_1_permit) = generate_user_1(deps.as_mut());
+        let (user_2, user_2_permit) = generate_user_2(deps.as_mut());
+
+        // User 1 store a new file
+        let payload = String::from("{\"file\": \"content\"}");
+        let evm_message = _create_evm_message(deps.as_ref(), &payload, &user_1_permit);
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res_store_file = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res_store_file.is_ok());
+
+        let file_id = _query_user_files(deps.as_ref(), &user_1_permit)[0].clone();
+
+        // User 1 creates a folder shared with user 2, and a sub-folder
+        let evm_message = _create_action_evm_message(
+            deps.as_ref(),
+            &user_1_permit,
+            ExecuteMsgAction::CreateFolder { name: String::from("Board"), parent: None }
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res.is_ok());
+
+        let query_msg = QueryMsg::WithPermit { 
+            permit: user_1_permit.clone(),
+            signature_scheme: SignatureScheme::Metamask,
+            query: QueryWithPermit::GetFolderIds {},
+            public_key: None
+        };
+        let response = query(deps.as_ref(), mock_env(), query_msg).unwrap();
+        let folder_ids: FolderIdsResponse = from_binary(&response).unwrap();
+        assert_eq!(folder_ids.folder_ids.len(), 1);
+        let folder_id = folder_ids.folder_ids[0].clone();
+
+        let evm_message = _create_action_evm_message(
+            deps.as_ref(),
+            &user_1_permit,
+            ExecuteMsgAction::CreateFolder { name: String::from("2024"), parent: Some(folder_id.clone()) }
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res.is_ok());
+
+        let evm_message = _create_action_evm_message(
+            deps.as_ref(),
+            &user_1_permit,
+            ExecuteMsgAction::ManageFolderRights { 
+                folder_id: folder_id.clone(), 
+                add_viewing: Vec::from([user_2.clone()]), 
+                delete_viewing: Vec::new() 
+            }
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res.is_ok());
+
+        // User 2 can see the folder and its sub-folder
+        let query_msg = QueryMsg::WithPermit { 
+            permit: user_2_permit.clone(),
+            signature_scheme: SignatureScheme::Metamask,
+            query: QueryWithPermit::GetFolder { folder_id: folder_id.clone() },
+            public_key: None
+        };
+        let response = query(deps.as_ref(), mock_env(), query_msg).unwrap();
+        let folder: FolderResponse = from_binary(&response).unwrap();
+        assert_eq!(folder.name, String::from("Board"));
+        assert!(folder.file_ids.is_empty());
+        assert_eq!(folder.folder_ids.len(), 1);
+        let sub_folder_id = folder.folder_ids[0].clone();
+
+        // The file is not in the folder yet
+        let query_msg = QueryMsg::WithPermit { 
+            permit: user_2_permit.clone(),
+            signature_scheme: SignatureScheme::Metamask,
+            query: QueryWithPermit::GetFileContent { file_id: file_id.clone() },
+            public_key: None
+        };
+        assert!(query(deps.as_ref(), mock_env(), query_msg.clone()).is_err());
+
+        // User 2 cannot move the file
+        let evm_message = _create_action_evm_message(
+            deps.as_ref(),
+            &user_2_permit,
+            ExecuteMsgAction::MoveFile { file_id: file_id.clone(), folder_id: Some(sub_folder_id.clone()) }
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert_eq!(res.unwrap_err(), ContractError::UnauthorizedAccess);
+
+        // User 1 moves the file in the sub-folder, user 2 sees it through the parent folder
+        let evm_message = _create_action_evm_message(
+            deps.as_ref(),
+            &user_1_permit,
+            ExecuteMsgAction::MoveFile { file_id: file_id.clone(), folder_id: Some(sub_folder_id.clone()) }
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res.is_ok());
+
+        let file_content = _query_file(deps.as_ref(), user_2_permit.clone(), &file_id);
+        assert_eq!(file_content, payload);
+
+        let query_msg_folder = QueryMsg::WithPermit { 
+            permit: user_2_permit.clone(),
+            signature_scheme: SignatureScheme::Metamask,
+            query: QueryWithPermit::GetFolder { folder_id: sub_folder_id.clone() },
+            public_key: None
+        };
+        let response = query(deps.as_ref(), mock_env(), query_msg_folder).unwrap();
+        let sub_folder: FolderResponse = from_binary(&response).unwrap();
+        assert_eq!(sub_folder.parent, Some(folder_id.clone()));
+        assert_eq!(sub_folder.file_ids, Vec::from([file_id.clone()]));
+
+        // A folder cannot be moved inside its sub-folder
+        let evm_message = _create_action_evm_message(
+            deps.as_ref(),
+            &user_1_permit,
+            ExecuteMsgAction::MoveFolder { folder_id: folder_id.clone(), parent: Some(sub_folder_id.clone()) }
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res.is_err());
+
+        // Once moved out of the folder, user 2 cannot see the file anymore
+        let evm_message = _create_action_evm_message(
+            deps.as_ref(),
+            &user_1_permit,
+            ExecuteMsgAction::MoveFile { file_id: file_id.clone(), folder_id: None }
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res.is_ok());
+
+        assert!(query(deps.as_ref(), mock_env(), query_msg).is_err());
+
+        // User 1 still have access to the file
+        let file_content = _query_file(deps.as_ref(), user_1_permit.clone(), &file_id);
+        assert_eq!(file_content, payload);
+
+        // A manager of the file cannot move it in his own folder to keep an access
+        let user_2_env = mock_info(user_2.as_str(), &coins(0, "token"));
+        let msg = ExecuteMsg::Direct { execute: ExecuteMsgAction::CreateFolder { name: String::from("Mine"), parent: None } };
+        let res = execute(deps.as_mut(), mock_env(), user_2_env, msg);
+        assert!(res.is_ok());
+
+        let query_msg_folders = QueryMsg::WithPermit { 
+            permit: user_2_permit.clone(),
+            signature_scheme: SignatureScheme::Metamask,
+            query: QueryWithPermit::GetFolderIds {},
+            public_key: None
+        };
+        let response = query(deps.as_ref(), mock_env(), query_msg_folders).unwrap();
+        let user_2_folder_ids: FolderIdsResponse = from_binary(&response).unwrap();
+        let user_2_folder_id = user_2_folder_ids.folder_ids.into_iter()
+            .find(|id| *id != folder_id && *id != sub_folder_id)
+            .unwrap();
+
+        let evm_message = _create_action_evm_message(
+            deps.as_ref(),
+            &user_1_permit,
+            ExecuteMsgAction::ManageFileRights {
+                file_id: file_id.clone(),
+                add_viewing: Vec::new(),
+                delete_viewing: Vec::new(),
+                change_owner: None,
+                roles: Vec::from([RoleAssignment { address: user_2.clone(), role: FileRole::Manager, expires_at: None }]),
+                add_groups: Vec::new(),
+                delete_groups: Vec::new(),
+                rekey: false
+            }
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res.is_ok());
+
+        let user_2_env = mock_info(user_2.as_str(), &coins(0, "token"));
+        let msg = ExecuteMsg::Direct { execute: ExecuteMsgAction::MoveFile { file_id: file_id.clone(), folder_id: Some(user_2_folder_id) } };
+        let res = execute(deps.as_mut(), mock_env(), user_2_env, msg);
+        assert_eq!(res.unwrap_err(), ContractError::UnauthorizedAccess);
+    }
+
+    #[test]
+    fn test_folder_limits() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        // Generate user info
+        let (user_1, _) = generate_user_1(deps.as_mut());
+
+        let user_1_execute = |deps: DepsMut, action: ExecuteMsgAction| {
+            let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+            execute(deps, mock_env(), user_1_env, ExecuteMsg::Direct { execute: action })
+        };
+
+        // The name is bounded
+        let res = user_1_execute(deps.as_mut(), ExecuteMsgAction::CreateFolder { name: "a".repeat(MAX_FOLDER_NAME_LENGTH + 1), parent: None });
+        assert_eq!(res.unwrap_err(), ContractError::CustomError { val: String::from("The name of a folder cannot exceed 128 bytes") });
+
+        // The nesting is bounded
+        let mut parent: Option<String> = None;
+        for depth in 0..MAX_FOLDER_DEPTH {
+            let res = user_1_execute(deps.as_mut(), ExecuteMsgAction::CreateFolder { name: format!("Level {}", depth), parent: parent.clone() });
+            assert!(res.is_ok());
+            let folder_key = *USER_FOLDERS.get(deps.as_ref().storage, &user_1).unwrap().last().unwrap();
+            parent = Some(hex::encode(folder_key));
+        }
+        let folder_ids: Vec<String> = USER_FOLDERS.get(deps.as_ref().storage, &user_1).unwrap().iter().map(hex::encode).collect();
+
+        let res = user_1_execute(deps.as_mut(), ExecuteMsgAction::CreateFolder { name: String::from("Too deep"), parent });
+        assert_eq!(res.unwrap_err(), ContractError::CustomError { val: String::from("The folders cannot be nested more than 8 levels deep") });
+
+        // Including when a folder and its sub-folder are moved
+        let res = user_1_execute(deps.as_mut(), ExecuteMsgAction::CreateFolder { name: String::from("Other"), parent: None });
+        assert!(res.is_ok());
+        let other_id = hex::encode(USER_FOLDERS.get(deps.as_ref().storage, &user_1).unwrap().last().unwrap());
+        let res = user_1_execute(deps.as_mut(), ExecuteMsgAction::CreateFolder { name: String::from("Child"), parent: Some(other_id.clone()) });
+        assert!(res.is_ok());
+
+        let res = user_1_execute(deps.as_mut(), ExecuteMsgAction::MoveFolder { 
+            folder_id: other_id.clone(), 
+            parent: Some(folder_ids[MAX_FOLDER_DEPTH - 2].clone()) 
+        });
+        assert_eq!(res.unwrap_err(), ContractError::CustomError { val: String::from("The folders cannot be nested more than 8 levels deep") });
+
+        let res = user_1_execute(deps.as_mut(), ExecuteMsgAction::MoveFolder { 
+            folder_id: other_id, 
+            parent: Some(folder_ids[MAX_FOLDER_DEPTH - 3].clone()) 
+        });
+        assert!(res.is_ok());
+
+        // The number of files in a folder is bounded
+        let res = user_1_execute(deps.as_mut(), ExecuteMsgAction::StoreNewFile { payload: String::from("ipfs://content"), info: None });
+        assert!(res.is_ok());
+        let file_id = query_file_ids(deps.as_ref(), &mock_env().block, user_1.clone()).unwrap().file_ids[0].clone();
+
+        let folder_key: [u8; 32] = hex::decode(&folder_ids[0]).unwrap().try_into().unwrap();
+        let mut folder = FOLDERS.get(deps.as_ref().storage, &folder_key).unwrap();
+        folder.files = (0..MAX_FOLDER_FILES).map(|index| [index as u8; 32]).collect();
+        FOLDERS.insert(deps.as_mut().storage, &folder_key, &folder).unwrap();
+
+        let res = user_1_execute(deps.as_mut(), ExecuteMsgAction::MoveFile { file_id, folder_id: Some(folder_ids[0].clone()) });
+        assert_eq!(res.unwrap_err(), ContractError::CustomError { val: String::from("A folder cannot contain more than 256 files") });
+    }
+
+    #[test]
+    fn test_admin_pause_contract() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        // Generate user info
+        let (_user_1, user_1_permit) = generate_user_1(deps.as_mut());
+
+        let payload = String::from("{\"file\": \"content\"}");
+        let evm_message = _create_evm_message(deps.as_ref(), &payload, &user_1_permit);
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res_store_file = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res_store_file.is_ok());
+
+        // The creator of the contract is the admin
+        let response = query(deps.as_ref(), mock_env(), QueryMsg::GetContractStatus {}).unwrap();
+        let status: ContractStatusResponse = from_binary(&response).unwrap();
+        assert_eq!(status, ContractStatusResponse {
+            admin: Addr::unchecked("creator"),
+            writes_paused: false,
+            reads_paused: false,
+        });
+
+        // Only the admin can pause the contract
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, ExecuteMsg::PauseWrites { paused: true });
+        assert_eq!(res.unwrap_err(), ContractError::UnauthorizedAdmin);
+
+        let admin_env = mock_info("creator", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), admin_env, ExecuteMsg::PauseWrites { paused: true });
+        assert!(res.is_ok());
+
+        // No file can be stored, but the files can still be read
+        let evm_message = _create_evm_message(deps.as_ref(), &payload, &user_1_permit);
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert_eq!(res.unwrap_err(), ContractError::ContractPaused);
+
+        let user_1_file = _query_user_files(deps.as_ref(), &user_1_permit);
+        assert_eq!(user_1_file.len(), 1);
+
+        // Pause the reads
+        let admin_env = mock_info("creator", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), admin_env, ExecuteMsg::PauseReads { paused: true });
+        assert!(res.is_ok());
+
+        let query_msg = QueryMsg::WithPermit { 
+            permit: user_1_permit.clone(),
+            signature_scheme: SignatureScheme::Metamask,
+            query: QueryWithPermit::GetFileIds {},
+            public_key: None
+        };
+        let res = query(deps.as_ref(), mock_env(), query_msg);
+        assert_eq!(res.unwrap_err(), ContractError::ContractPaused);
+
+        // The new admin must be a valid address
+        let admin_env = mock_info("creator", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), admin_env, ExecuteMsg::ChangeAdmin { new_admin: Addr::unchecked("New_Admin") });
+        assert!(matches!(res.unwrap_err(), ContractError::Std(_)));
+
+        // Hand the admin role to someone else
+        let admin_env = mock_info("creator", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), admin_env, ExecuteMsg::ChangeAdmin { new_admin: Addr::unchecked("new_admin") });
+        assert!(res.is_ok());
+
+        let admin_env = mock_info("creator", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), admin_env, ExecuteMsg::PauseReads { paused: false });
+        assert_eq!(res.unwrap_err(), ContractError::UnauthorizedAdmin);
+
+        let admin_env = mock_info("new_admin", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), admin_env.clone(), ExecuteMsg::PauseReads { paused: false });
+        assert!(res.is_ok());
+        let res = execute(deps.as_mut(), mock_env(), admin_env, ExecuteMsg::PauseWrites { paused: false });
+        assert!(res.is_ok());
+
+        let response = query(deps.as_ref(), mock_env(), QueryMsg::GetContractStatus {}).unwrap();
+        let status: ContractStatusResponse = from_binary(&response).unwrap();
+        assert_eq!(status, ContractStatusResponse {
+            admin: Addr::unchecked("new_admin"),
+            writes_paused: false,
+            reads_paused: false,
+        });
+
+        // The contract works again
+        let other_payload = String::from("{\"file\": \"other content\"}");
+        let evm_message = _create_evm_message(deps.as_ref(), &other_payload, &user_1_permit);
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res.is_ok());
+
+        let user_1_file = _query_user_files(deps.as_ref(), &user_1_permit);
+        assert_eq!(user_1_file.len(), 2);
+    }
+
+    #[test]
+    fn test_message_origin_allowlist() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        // Generate user info
+        let (_user_1, user_1_permit) = generate_user_1(deps.as_mut());
+
+        let payload = String::from("{\"file\": \"content\"}");
+        let evm_message = _create_evm_message(deps.as_ref(), &payload, &user_1_permit);
+
+        // A message relayed by an unknown sender is rejected
+        let attacker_env = mock_info("attacker", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), attacker_env, evm_message.clone());
+        assert_eq!(res.unwrap_err(), ContractError::UnauthorizedOrigin);
+
+        // A message from an unknown source contract is rejected
+        let other_source_message = match evm_message.clone() {
+            ExecuteMsg::ReceiveMessageCosmos { source_chain, payload, .. } => ExecuteMsg::ReceiveMessageCosmos {
+                source_chain,
+                source_address: String::from("0x0000000000000000000000000000000000000001"),
+                payload,
+            },
+            _ => panic!("Unexpected message"),
+        };
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, other_source_message);
+        assert_eq!(res.unwrap_err(), ContractError::UnauthorizedOrigin);
+
+        assert!(_query_user_files(deps.as_ref(), &user_1_permit).is_empty());
+
+        // Only the admin can manage the allowlist
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let msg = ExecuteMsg::UpdateAllowedRelayers { add: Vec::from([Addr::unchecked("attacker")]), remove: Vec::new() };
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, msg);
+        assert_eq!(res.unwrap_err(), ContractError::UnauthorizedAdmin);
+
+        let response = query(deps.as_ref(), mock_env(), QueryMsg::GetAllowedOrigins {}).unwrap();
+        let origins: AllowedOriginsResponse = from_binary(&response).unwrap();
+        assert_eq!(origins.relayers, Vec::from([Addr::unchecked("anyone")]));
+        assert_eq!(origins.sources, Vec::from([AllowedSource {
+            source_chain: String::from("polygon"),
+            source_address: String::from("0x329cdcbbd82c934fe32322b423bd8fbd30b4eeb6"),
+        }]));
+
+        // The allowed relayer and source can store a file
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message.clone());
+        assert!(res.is_ok());
+        assert_eq!(_query_user_files(deps.as_ref(), &user_1_permit).len(), 1);
+
+        // Once the source is removed, its messages are rejected
+        let admin_env = mock_info("creator", &coins(0, "token"));
+        let msg = ExecuteMsg::UpdateAllowedSources { 
+            add: Vec::new(), 
+            remove: Vec::from([AllowedSource {
+                source_chain: String::from("Polygon"),
+                source_address: String::from("0x329CdCBBD82c934fe32322b423bD8fBd30b4EEB6"),
+            }])
+        };
+        let res = execute(deps.as_mut(), mock_env(), admin_env, msg);
+        assert!(res.is_ok());
+
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert_eq!(res.unwrap_err(), ContractError::UnauthorizedOrigin);
+    }
+
+    #[test]
+    fn test_replay_protection() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        // Generate user info
+        let (user_1, user_1_permit) = generate_user_1(deps.as_mut());
+        let (user_2, user_2_permit) = generate_user_2(deps.as_mut());
+
+        // User 1 store a new file and share it with user 2
+        let payload = String::from("{\"file\": \"content\"}");
+        let evm_message = _create_evm_message(deps.as_ref(), &payload, &user_1_permit);
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res_store_file = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res_store_file.is_ok());
+
+        let file_id = _query_user_files(deps.as_ref(), &user_1_permit)[0].clone();
+
+        let share_message = _create_manage_request_evm_message(
+            deps.as_ref(),
+            &user_1_permit,
+            file_id.clone(),
+            Vec::from([user_2.clone()]),
+            Vec::new(),
+            user_1.clone()
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, share_message.clone());
+        assert!(res.is_ok());
+
+        // User 1 revokes the access of user 2
+        let evm_message = _create_manage_request_evm_message(
+            deps.as_ref(),
+            &user_1_permit,
+            file_id.clone(),
+            Vec::new(),
+            Vec::from([user_2.clone()]),
+            user_1.clone()
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res.is_ok());
+
+        // Replaying the sharing message does not give the access back
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, share_message);
+        assert_eq!(res.unwrap_err(), ContractError::MessageAlreadyProcessed);
+        assert!(_query_user_files(deps.as_ref(), &user_2_permit).is_empty());
+
+        // A message after its deadline is rejected
+        let mut env = mock_env();
+        let evm_message = _create_action_evm_message_with_deadline(
+            deps.as_ref(),
+            &user_1_permit,
+            ExecuteMsgAction::StoreNewFile { payload: payload.clone(), info: None },
+            Some(Expiration::AtHeight(env.block.height + 10))
+        );
+        env.block.height = env.block.height + 10;
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), env, unauth_env, evm_message.clone());
+        assert_eq!(res.unwrap_err(), ContractError::MessageExpired);
+
+        // The same message before its deadline is accepted
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message.clone());
+        assert!(res.is_ok());
+        assert_eq!(_query_user_files(deps.as_ref(), &user_1_permit).len(), 2);
+
+        // Only the admin can prune the processed messages
+        let prune_msg = ExecuteMsg::PruneProcessedMessages { start_page: 0, page_size: 10 };
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, prune_msg.clone());
+        assert_eq!(res.unwrap_err(), ContractError::UnauthorizedAdmin);
+
+        // Before the deadline, nothing is pruned
+        let admin_env = mock_info("creator", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), admin_env, prune_msg.clone());
+        assert!(res.is_ok());
+        assert_eq!(PROCESSED_MESSAGES.get_len(deps.as_ref().storage).unwrap(), 4);
+
+        // After it, only the message with a deadline is pruned, and it is still rejected
+        let mut env = mock_env();
+        env.block.height = env.block.height + 10;
+        let admin_env = mock_info("creator", &coins(0, "token"));
+        let res = execute(deps.as_mut(), env.clone(), admin_env, prune_msg);
+        assert!(res.is_ok());
+        assert_eq!(PROCESSED_MESSAGES.get_len(deps.as_ref().storage).unwrap(), 3);
+
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), env, unauth_env, evm_message);
+        assert_eq!(res.unwrap_err(), ContractError::MessageExpired);
+    }
+
+    #[test]
+    fn test_revoke_permit() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        // Generate user info
+        let (user_1, user_1_permit) = generate_user_1(deps.as_mut());
+        let (user_2, user_2_permit) = generate_user_2(deps.as_mut());
+
+        // The permits can be revoked while the contract is paused
+        let admin_env = mock_info("creator", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), admin_env, ExecuteMsg::PauseWrites { paused: true });
+        assert!(res.is_ok());
+
+        // User 1 revokes his permit through an encrypted message
+        let evm_message = _create_action_evm_message(
+            deps.as_ref(),
+            &user_1_permit,
+            ExecuteMsgAction::RevokePermit { permit_name: user_1_permit.params.permit_name.clone() }
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert_eq!(res.unwrap(), Response::default());
+
+        // User 2 revokes other permits directly
+        let user_2_env = mock_info(user_2.as_str(), &coins(0, "token"));
+        let msg = ExecuteMsg::RevokePermit { permit_name: String::from("LEAKED_PERMIT") };
+        let res = execute(deps.as_mut(), mock_env(), user_2_env, msg);
+        assert_eq!(res.unwrap(), Response::default());
+
+        let user_2_env = mock_info(user_2.as_str(), &coins(0, "token"));
+        let msg = ExecuteMsg::Direct { execute: ExecuteMsgAction::RevokePermit { permit_name: String::from("OTHER_PERMIT") } };
+        let res = execute(deps.as_mut(), mock_env(), user_2_env, msg);
+        assert_eq!(res.unwrap(), Response::default());
+
+        // The other messages are still rejected
+        let payload = String::from("{\"file\": \"content\"}");
+        let evm_message = _create_evm_message(deps.as_ref(), &payload, &user_2_permit);
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert_eq!(res.unwrap_err(), ContractError::ContractPaused);
+
+        let user_2_env = mock_info(user_2.as_str(), &coins(0, "token"));
+        let msg = ExecuteMsg::Direct { execute: ExecuteMsgAction::StoreNewFile { payload: payload.clone(), info: None } };
+        let res = execute(deps.as_mut(), mock_env(), user_2_env, msg);
+        assert_eq!(res.unwrap_err(), ContractError::ContractPaused);
+
+        let admin_env = mock_info("creator", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), admin_env, ExecuteMsg::PauseWrites { paused: false });
+        assert!(res.is_ok());
+
+        // The permit of user 1 cannot be used anymore
+        let evm_message = _create_evm_message(deps.as_ref(), &payload, &user_1_permit);
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert_eq!(res.unwrap_err(), ContractError::Std(StdError::generic_err(format!(
+            "Permit {:?} was revoked by account {:?}",
+            user_1_permit.params.permit_name,
+            user_1.as_str()
+        ))));
+
+        // Neither for the queries
+        let query_msg = QueryMsg::WithPermit { 
+            permit: user_1_permit.clone(),
+            signature_scheme: SignatureScheme::Metamask,
+            query: QueryWithPermit::GetFileIds {},
+            public_key: None
+        };
+        let res = query(deps.as_ref(), mock_env(), query_msg);
+        assert_eq!(res.unwrap_err(), ContractError::Std(StdError::generic_err(format!(
+            "Permit {:?} was revoked by account {:?}",
+            user_1_permit.params.permit_name,
+            user_1.as_str()
+        ))));
+
+        // User 2 can still use his other permit, and see the revoked ones
+        let query_msg = QueryMsg::WithPermit { 
+            permit: user_2_permit.clone(),
+            signature_scheme: SignatureScheme::Metamask,
+            query: QueryWithPermit::GetRevokedPermits {},
+            public_key: None
+        };
+        let response = query(deps.as_ref(), mock_env(), query_msg).unwrap();
+        let revoked_permits: RevokedPermitsResponse = from_binary(&response).unwrap();
+        assert_eq!(revoked_permits.permit_names, Vec::from([String::from("LEAKED_PERMIT"), String::from("OTHER_PERMIT")]));
+
+        let evm_message = _create_evm_message(deps.as_ref(), &payload, &user_2_permit);
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert_eq!(res.unwrap(), Response::default());
+    }
+
+    #[test]
+    fn test_permit_permissions() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        // Generate user info
+        let (user_3, _user_3_permit) = generate_user_3(deps.as_mut());
+        let list_permit = generate_user_3_list_permit(deps.as_mut());
+
+        // User 3 store a new file
+        let payload = String::from("{\"file\": \"content\"}");
+        let user_3_env = mock_info(user_3.as_str(), &coins(0, "token"));
+        let msg = ExecuteMsg::Direct { execute: ExecuteMsgAction::StoreNewFile { payload: payload.clone(), info: None } };
+        let res = execute(deps.as_mut(), mock_env(), user_3_env, msg);
+        assert!(res.is_ok());
+
+        // A permit only allowing to list the files can list them
+        let query_msg = QueryMsg::WithPermit { 
+            permit: list_permit.clone(),
+            signature_scheme: SignatureScheme::Amino,
+            query: QueryWithPermit::GetFileIds {},
+            public_key: None
+        };
+        let response = query(deps.as_ref(), mock_env(), query_msg).unwrap();
+        let file_ids: FileIdsResponse = from_binary(&response).unwrap();
+        assert_eq!(file_ids.file_ids.len(), 1);
+        let file_id = file_ids.file_ids[0].clone();
+
+        // But it cannot be used for anything else
+        let actions = Vec::from([
+            (ExecuteMsgAction::StoreNewFile { payload: payload.clone(), info: None }, "Write"),
+            (ExecuteMsgAction::ProposeOwnershipTransfer { 
+                file_id: file_id.clone(), 
+                new_owner: Addr::unchecked("secret1u69n2nxq3495t8lw2usn0qgg685ad7d9j0w6lq") 
+            }, "Transfer"),
+            (ExecuteMsgAction::ManageFileRights { 
+                file_id: file_id.clone(), 
+                add_viewing: Vec::from([Addr::unchecked("secret1u69n2nxq3495t8lw2usn0qgg685ad7d9j0w6lq")]), 
+                delete_viewing: Vec::new(), 
+                change_owner: None,
+                roles: Vec::new(),
+                add_groups: Vec::new(),
+                delete_groups: Vec::new(),
+                rekey: false
+            }, "Manage"),
+            (ExecuteMsgAction::DeleteFile { file_id: file_id.clone(), tombstone: false }, "Owner"),
+        ]);
+
+        for (action, permission) in actions {
+            let evm_message = _create_permit_evm_message(
+                deps.as_ref(),
+                &ExecutePermitMsg::WithPermit { 
+                    permit: list_permit.clone(), 
+                    signature_scheme: SignatureScheme::Amino,
+                    execute: action,
+                    deadline: None
+                }
+            );
+            let unauth_env = mock_info("anyone", &coins(0, "token"));
+            let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+            assert_eq!(res.unwrap_err(), ContractError::MissingPermitPermission { val: String::from(permission) });
+        }
+
+        let queries = Vec::from([
+            (QueryWithPermit::GetFileContent { file_id: file_id.clone() }, "Read"),
+            (QueryWithPermit::GetFileAccess { file_id: file_id.clone() }, "Manage"),
+        ]);
+
+        for (query_with_permit, permission) in queries {
+            let query_msg = QueryMsg::WithPermit { 
+                permit: list_permit.clone(),
+                signature_scheme: SignatureScheme::Amino,
+                query: query_with_permit,
+                public_key: None
+            };
+            let res = query(deps.as_ref(), mock_env(), query_msg);
+            assert_eq!(res.unwrap_err(), ContractError::MissingPermitPermission { val: String::from(permission) });
+        }
+
+        // Nothing has changed
+        let query_msg = QueryMsg::WithPermit { 
+            permit: list_permit.clone(),
+            signature_scheme: SignatureScheme::Amino,
+            query: QueryWithPermit::GetFileIds {},
+            public_key: None
+        };
+        let response = query(deps.as_ref(), mock_env(), query_msg).unwrap();
+        let file_ids: FileIdsResponse = from_binary(&response).unwrap();
+        assert_eq!(file_ids.file_ids, Vec::from([file_id.clone()]));
+
+        // A permit whose scope was widened after the signature is rejected
+        let mut forged_permit = list_permit.clone();
+        forged_permit.params.permissions = Vec::from([DocumentPermission::Owner]);
+        let query_msg = QueryMsg::WithPermit { 
+            permit: forged_permit,
+            signature_scheme: SignatureScheme::Amino,
+            query: QueryWithPermit::GetFileContent { file_id: file_id.clone() },
+            public_key: None
+        };
+        let res = query(deps.as_ref(), mock_env(), query_msg);
+        assert_eq!(
+            res.unwrap_err(), 
+            ContractError::Std(StdError::generic_err("Failed to verify signatures for the given permit"))
+        );
+
+        // As well as a permit for another contract
+        let mut other_token_permit = list_permit;
+        other_token_permit.params.allowed_tokens = Vec::from([String::from("secret1u69n2nxq3495t8lw2usn0qgg685ad7d9j0w6lq")]);
+        let query_msg = QueryMsg::WithPermit { 
+            permit: other_token_permit,
+            signature_scheme: SignatureScheme::Amino,
+            query: QueryWithPermit::GetFileIds {},
+            public_key: None
+        };
+        let res = query(deps.as_ref(), mock_env(), query_msg);
+        assert!(matches!(res.unwrap_err(), ContractError::Std(_)));
+    }
+
+    #[test]
+    fn test_keplr_amino_permit() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        // Generate user info
+        let (user_3, user_3_permit) = generate_user_3(deps.as_mut());
+
+        // The permit is only valid with the amino scheme
+        let contract_address = CONFIG.load(deps.as_mut().storage).unwrap().contract_address;
+        let chain_id = mock_env().block.chain_id;
+        let account = _verify_permit(deps.as_ref(), user_3_permit.clone(), SignatureScheme::Amino, contract_address.clone(), &chain_id);
+        assert_eq!(account.unwrap(), user_3);
+        let account = _verify_permit(deps.as_ref(), user_3_permit.clone(), SignatureScheme::Metamask, contract_address, &chain_id);
+        assert!(account.is_err());
+
+        // User 3 store a new file with his Keplr permit
+        let payload = String::from("{\"file\": \"content\"}");
+        let evm_message = _create_permit_evm_message(
+            deps.as_ref(),
+            &ExecutePermitMsg::WithPermit { 
+                permit: user_3_permit.clone(), 
+                signature_scheme: SignatureScheme::Amino,
+                execute: ExecuteMsgAction::StoreNewFile { payload: payload.clone(), info: None },
+                deadline: None
+            }
+        );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res_store_file = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res_store_file.is_ok());
+
+        // User 3 retrieves his file
+        let query_msg = QueryMsg::WithPermit { 
+            permit: user_3_permit.clone(),
+            signature_scheme: SignatureScheme::Amino,
+            query: QueryWithPermit::GetFileIds {},
+            public_key: None
+        };
+        let response = query(deps.as_ref(), mock_env(), query_msg).unwrap();
+        let file_ids: FileIdsResponse = from_binary(&response).unwrap();
+        assert_eq!(file_ids.file_ids.len(), 1);
+
+        let query_msg = QueryMsg::WithPermit { 
+            permit: user_3_permit.clone(),
+            signature_scheme: SignatureScheme::Amino,
+            query: QueryWithPermit::GetFileContent { file_id: file_ids.file_ids[0].clone() },
+            public_key: None
+        };
+        let response = query(deps.as_ref(), mock_env(), query_msg).unwrap();
+        let file_content: FilePayloadResponse = from_binary(&response).unwrap();
+        assert_eq!(file_content.payload, payload);
+    }
+
+    #[test]
+    fn test_eip712_permit() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        // Permit of user 3 signed as EIP-712 typed data, for the secret-4 chain
+        let (user_3, mut user_3_permit) = generate_user_3(deps.as_mut());
+        user_3_permit.params.permit_name = String::from("SECRET_DOCUMENT_PERMIT_EIP712");
+        user_3_permit.signature.signature = Binary::from_base64(
+            "iPSrAuwLthhVp6y6Suu0p9ojYDIjJjAxJ8K2TU9Wf2RufbDKRYUzWZrKTz1e75CfBAx+mf5QmTZxTlhZ8xsu1g=="
         ).unwrap();
 
-        // Query the contract public key
-        let contract_public_key = _query_contract_pubic_key(deps).public_key;
+        let contract_address = CONFIG.load(deps.as_mut().storage).unwrap().contract_address;
+        let account = _verify_permit(deps.as_ref(), user_3_permit.clone(), SignatureScheme::Eip712, contract_address.clone(), "secret-4");
+        assert_eq!(account.unwrap(), user_3);
 
-        // Generate public/private key locally
-        let (local_public_key, local_private_key) = _generate_local_public_private_key(mock_env());
+        // The domain is tied to the chain
+        let account = _verify_permit(deps.as_ref(), user_3_permit.clone(), SignatureScheme::Eip712, contract_address, "pulsar-3");
+        assert!(account.is_err());
 
-        // Create share secret
-        let encrypted_message = _encrypt_with_share_secret(
-            local_private_key, 
-            contract_public_key, 
-            message
+        // User 3 store a new file and retrieves it
+        let mut env = mock_env();
+        env.block.chain_id = String::from("secret-4");
+
+        let payload = String::from("{\"file\": \"content\"}");
+        let evm_message = _create_permit_evm_message(
+            deps.as_ref(),
+            &ExecutePermitMsg::WithPermit { 
+                permit: user_3_permit.clone(), 
+                signature_scheme: SignatureScheme::Eip712,
+                execute: ExecuteMsgAction::StoreNewFile { payload: payload.clone(), info: None },
+                deadline: None
+            }
         );
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res_store_file = execute(deps.as_mut(), env.clone(), unauth_env, evm_message);
+        assert!(res_store_file.is_ok());
 
-        // Create the request
-        ExecuteMsg::ReceiveMessageCosmos {
-            source_chain: String::from("polygon"),
-            source_address: String::from("0x329CdCBBD82c934fe32322b423bD8fBd30b4EEB6"),
-            payload: EncryptedExecuteMsg {
-                payload: encrypted_message,
-                public_key: local_public_key,
-            },
-        }
+        let query_msg = QueryMsg::WithPermit { 
+            permit: user_3_permit.clone(),
+            signature_scheme: SignatureScheme::Eip712,
+            query: QueryWithPermit::GetFileIds {},
+            public_key: None
+        };
+        let response = query(deps.as_ref(), env, query_msg).unwrap();
+        let file_ids: FileIdsResponse = from_binary(&response).unwrap();
+        assert_eq!(file_ids.file_ids.len(), 1);
     }
 
+    #[test]
+    fn test_direct_execute_messages() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
 
-    fn _create_manage_request_evm_message(
-        deps: Deps,
-        permit: &Permit,
-        file_id: String,
-        add_viewing: Vec<Addr>,
-        delete_viewing: Vec<Addr>,
-        change_owner: Addr
-    ) -> ExecuteMsg {
+        // Generate user info
+        let (user_1, user_1_permit) = generate_user_1(deps.as_mut());
+        let (user_2, user_2_permit) = generate_user_2(deps.as_mut());
 
-        // Create the message for storing a new file
-        let message = &Json::serialize(
-            &ExecutePermitMsg::WithPermit { 
-                permit: permit.clone(), 
-                execute: ExecuteMsgAction::ManageFileRights {
-                    file_id: file_id,
-                    add_viewing: add_viewing,
-                    delete_viewing: delete_viewing,
-                    change_owner: change_owner
-                }
+        // User 1 store a new file directly, without Axelar nor permit
+        let payload = String::from("{\"file\": \"content\"}");
+        let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+        let msg = ExecuteMsg::Direct { execute: ExecuteMsgAction::StoreNewFile { payload: payload.clone(), info: None } };
+        let res = execute(deps.as_mut(), mock_env(), user_1_env, msg);
+        assert!(res.is_ok());
+
+        let user_1_file = _query_user_files(deps.as_ref(), &user_1_permit);
+        assert_eq!(user_1_file.len(), 1);
+        let file_id = user_1_file[0].clone();
+
+        // User 2 cannot manage the file of user 1
+        let manage_msg = ExecuteMsg::Direct { 
+            execute: ExecuteMsgAction::ManageFileRights {
+                file_id: file_id.clone(),
+                add_viewing: Vec::from([user_2.clone()]),
+                delete_viewing: Vec::new(),
+                change_owner: None,
+                roles: Vec::new(),
+                add_groups: Vec::new(),
+                delete_groups: Vec::new(),
+                rekey: false
             }
-        ).unwrap();
+        };
+        let user_2_env = mock_info(user_2.as_str(), &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), user_2_env, manage_msg.clone());
+        assert_eq!(res.unwrap_err(), ContractError::UnauthorizedAccess);
 
-        // Query the contract public key
-        let contract_public_key = _query_contract_pubic_key(deps).public_key;
+        // User 1 shares the file with user 2
+        let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), user_1_env, manage_msg);
+        assert!(res.is_ok());
 
-        // Generate public/private key locally
-        let (local_public_key, local_private_key) = _generate_local_public_private_key(mock_env());
+        let file_content = _query_file(deps.as_ref(), user_2_permit.clone(), &file_id);
+        assert_eq!(file_content, payload);
 
-        // Create share secret
-        let encrypted_message = _encrypt_with_share_secret(
-            local_private_key, 
-            contract_public_key, 
-            message
-        );
+        // The direct messages are not executed while the contract is paused
+        let admin_env = mock_info("creator", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), admin_env, ExecuteMsg::PauseWrites { paused: true });
+        assert!(res.is_ok());
 
-        // Create the request
-        ExecuteMsg::ReceiveMessageCosmos {
-            source_chain: String::from("polygon"),
-            source_address: String::from("0x329CdCBBD82c934fe32322b423bD8fBd30b4EEB6"),
-            payload: EncryptedExecuteMsg {
-                payload: encrypted_message,
-                public_key: local_public_key,
-            },
-        }
+        let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+        let msg = ExecuteMsg::Direct { execute: ExecuteMsgAction::StoreNewFile { payload: payload.clone(), info: None } };
+        let res = execute(deps.as_mut(), mock_env(), user_1_env, msg);
+        assert_eq!(res.unwrap_err(), ContractError::ContractPaused);
     }
 
+    #[test]
+    fn test_wrapped_file_keys() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
 
+        // Generate user info
+        let (user_1, _) = generate_user_1(deps.as_mut());
+        let (user_2, user_2_permit) = generate_user_2(deps.as_mut());
 
-    fn _query_user_files(deps: Deps, permit: &Permit) -> Vec<String> {
-        let query_msg = QueryMsg::WithPermit { 
-            permit: permit.clone(),
-            query: QueryWithPermit::GetFileIds {}
-        };
-        let res = query(deps, mock_env(), query_msg);
+        // User 1 store a new file and shares it with user 2
+        let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+        let msg = ExecuteMsg::Direct { execute: ExecuteMsgAction::StoreNewFile { payload: String::from("ipfs://content"), info: None } };
+        let res = execute(deps.as_mut(), mock_env(), user_1_env, msg);
         assert!(res.is_ok());
-        
-        let file_id_response = Json::deserialize::<FileIdsResponse>(&res.unwrap()).map(Some);
 
-        // We should now have one key
-        file_id_response.unwrap().unwrap().file_ids
-    }
+        let user_1_files = query_file_ids(deps.as_ref(), &mock_env().block, user_1.clone()).unwrap().file_ids;
+        let file_id = user_1_files[0].clone();
+
+        let share_msg = |add_viewing: Vec<Addr>, delete_viewing: Vec<Addr>| ExecuteMsg::Direct { 
+            execute: ExecuteMsgAction::ManageFileRights {
+                file_id: file_id.clone(),
+                add_viewing,
+                delete_viewing,
+                change_owner: None,
+                roles: Vec::new(),
+                add_groups: Vec::new(),
+                delete_groups: Vec::new(),
+                rekey: false
+            }
+        };
+        let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), user_1_env, share_msg(Vec::from([user_2.clone()]), Vec::new()));
+        assert!(res.is_ok());
 
-    fn _query_file(deps: Deps, permit: Permit, file_key: &String) -> String {
-        let query_msg = QueryMsg::WithPermit { 
-            permit: permit,
-            query: QueryWithPermit::GetFileContent { file_id: file_key.clone() } 
+        // A key cannot be wrapped for a user without access
+        let add_keys_msg = |user: Addr, wrapped_key: &str| ExecuteMsg::Direct { 
+            execute: ExecuteMsgAction::AddFileKeys {
+                file_id: file_id.clone(),
+                keys: Vec::from([WrappedFileKey { user, wrapped_key: String::from(wrapped_key) }])
+            }
         };
+        let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), user_1_env, add_keys_msg(Addr::unchecked("no_access"), "key"));
+        assert!(res.is_err());
 
-        let response = query(deps, mock_env(), query_msg).unwrap();
-        let file_content: FilePayloadResponse = from_binary(&response).unwrap();
-        file_content.payload
-    }
+        // Only the owner or a manager can wrap a key
+        let user_2_env = mock_info(user_2.as_str(), &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), user_2_env, add_keys_msg(user_2.clone(), "key for user 2"));
+        assert_eq!(res.unwrap_err(), ContractError::UnauthorizedAccess);
+
+        let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), user_1_env, add_keys_msg(user_2.clone(), "key for user 2"));
+        assert!(res.is_ok());
 
-    fn _query_file_metadata(deps: Deps, permit: Permit, file_key: &String) -> FileAccessResponse {
+        // User 2 gets the shared content and his own wrapped key
         let query_msg = QueryMsg::WithPermit { 
-            permit: permit,
-            query: QueryWithPermit::GetFileAccess { file_id: file_key.clone() } 
+            permit: user_2_permit.clone(),
+            signature_scheme: SignatureScheme::Metamask,
+            query: QueryWithPermit::GetFileContent { file_id: file_id.clone() },
+            public_key: None
         };
+        let response = query(deps.as_ref(), mock_env(), query_msg.clone()).unwrap();
+        let file_content: FilePayloadResponse = from_binary(&response).unwrap();
+        assert_eq!(file_content, FilePayloadResponse {
+            payload: String::from("ipfs://content"),
+            wrapped_key: Some(String::from("key for user 2")),
+            rotated_at: None,
+            version: 0,
+        });
+
+        // Removing user 2 deletes his wrapped key
+        let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), user_1_env, share_msg(Vec::new(), Vec::from([user_2.clone()])));
+        assert!(res.is_ok());
 
-        let response = query(deps, mock_env(), query_msg).unwrap();
-        let file_content: FileAccessResponse = from_binary(&response).unwrap();
-        file_content
-    }
-
+        let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), user_1_env, share_msg(Vec::from([user_2.clone()]), Vec::new()));
+        assert!(res.is_ok());
 
-    #[test]
-    fn test_contract_initialization() {
-        // Initialize the smart contract
-        let mut deps = mock_dependencies();
-        setup_contract(deps.as_mut());
+        let response = query(deps.as_ref(), mock_env(), query_msg).unwrap();
+        let file_content: FilePayloadResponse = from_binary(&response).unwrap();
+        assert_eq!(file_content.wrapped_key, None);
 
-        // Check that the contract generate a public key
-        let key_response = _query_contract_pubic_key(deps.as_ref());
-        assert_eq!(33, key_response.public_key.len()); // We have an additional 1 byte prefix for the X-coordinate
-    }
+        // A key can be wrapped for a user seeing the file through a group
+        let (user_3, user_3_permit) = generate_user_3(deps.as_mut());
+        let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+        let msg = ExecuteMsg::Direct { execute: ExecuteMsgAction::CreateGroup { name: String::from("Board"), members: Vec::from([user_3.clone()]) } };
+        let res = execute(deps.as_mut(), mock_env(), user_1_env, msg);
+        assert!(res.is_ok());
 
+        let query_msg = |query: QueryWithPermit| QueryMsg::WithPermit { 
+            permit: user_3_permit.clone(),
+            signature_scheme: SignatureScheme::Amino,
+            query,
+            public_key: None
+        };
+        let response = query(deps.as_ref(), mock_env(), query_msg(QueryWithPermit::GetGroupIds {})).unwrap();
+        let group_id = from_binary::<GroupIdsResponse>(&response).unwrap().group_ids[0].clone();
+
+        let share_group_msg = ExecuteMsg::Direct { 
+            execute: ExecuteMsgAction::ManageFileRights {
+                file_id: file_id.clone(),
+                add_viewing: Vec::new(),
+                delete_viewing: Vec::new(),
+                change_owner: None,
+                roles: Vec::new(),
+                add_groups: Vec::from([group_id.clone()]),
+                delete_groups: Vec::new(),
+                rekey: false
+            }
+        };
+        let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), user_1_env, share_group_msg);
+        assert!(res.is_ok());
 
-    #[test]
-    fn keys_initialization() {
-        // Initialize the smart contract
-        let mut deps = mock_dependencies();
-        setup_contract(deps.as_mut());
-        
-        let key_response = _query_contract_pubic_key(deps.as_ref());
-        let public_key = key_response.public_key; 
+        let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), user_1_env, add_keys_msg(user_3.clone(), "key for user 3"));
+        assert!(res.is_ok());
 
-        // Verify that the public key is the same as the one we store
-        let contract_keys = CONTRACT_KEYS.load(deps.as_mut().storage);
-        let storage_public_key = match contract_keys {
-            Ok(keys) => keys.public_key,
-            Err(error) => panic!("Error when loading key from storage: {:?}", error),
+        let response = query(deps.as_ref(), mock_env(), query_msg(QueryWithPermit::GetFileContent { file_id: file_id.clone() })).unwrap();
+        let file_content: FilePayloadResponse = from_binary(&response).unwrap();
+        assert_eq!(file_content.wrapped_key, Some(String::from("key for user 3")));
+
+        // Once out of the group, the key is not served anymore
+        let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+        let msg = ExecuteMsg::Direct { 
+            execute: ExecuteMsgAction::UpdateGroupMembers { 
+                group_id, 
+                add_members: Vec::new(), 
+                remove_members: Vec::from([user_3.clone()]) 
+            } 
         };
-        assert!(public_key == storage_public_key);
+        let res = execute(deps.as_mut(), mock_env(), user_1_env, msg);
+        assert!(res.is_ok());
+
+        let res = query(deps.as_ref(), mock_env(), query_msg(QueryWithPermit::GetFileContent { file_id }));
+        assert_eq!(res.unwrap_err(), ContractError::Std(StdError::generic_err("Unauthorized access for the given file.")));
     }
 
     #[test]
-    fn test_evm_store_new_file() {
+    fn test_unpredictable_file_ids() {
         let mut deps = mock_dependencies();
         setup_contract(deps.as_mut());
 
-        // Generate user information & payload
-        let (_owner, user_permit) = generate_user_1(deps.as_mut());
-        let payload = String::from("{\"file\": \"content\"}");
+        // Generate user info
+        let (user_1, user_1_permit) = generate_user_1(deps.as_mut());
 
-        let evm_message = _create_evm_message(deps.as_ref(), &payload, &user_permit);
+        // A file stored before with a sequential id
+        let legacy_key: [u8; 32] = Sha256::digest(1u128.to_le_bytes()).into();
+        let mut file_storage = PrefixedStorage::new(deps.as_mut().storage, PREFIX_FILES);
+        file_storage.set(&legacy_key, b"{\"payload\":\"legacy file\"}");
+        let mut file_metadata_storage = PrefixedStorage::new(deps.as_mut().storage, PREFIX_FILES_METADATA);
+        let legacy_metadata = format!("{{\"owner\":\"{}\",\"viewers\":[\"{}\"]}}", user_1, user_1);
+        file_metadata_storage.set(&legacy_key, legacy_metadata.as_bytes());
+        let permission = FilePermission { role: FileRole::Owner, expires_at: None };
+        FILE_PERMISSIONS.insert(deps.as_mut().storage, &(legacy_key, user_1.clone()), &permission).unwrap();
+        add_file_key_to_user(deps.as_mut(), user_1.clone(), legacy_key).unwrap();
+
+        // The new files get an id derived from the randomness of the block
+        let mut env = mock_env();
+        env.block.random = Some(Binary::from([7u8; 32]));
+        let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+        let msg = ExecuteMsg::Direct { execute: ExecuteMsgAction::StoreNewFile { payload: String::from("new file"), info: None } };
+        let res = execute(deps.as_mut(), env, user_1_env, msg);
+        assert!(res.is_ok());
 
-        // Send the evm message
-        let unauth_env = mock_info("anyone", &coins(0, "token"));
-        let res_store_file = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
-        assert!(res_store_file.is_ok());
-        
-        // Query the user file
-        let user_file = _query_user_files(deps.as_ref(), &user_permit);
-        
-        // We should have the file we previously stored
-        assert_eq!(user_file.len(), 1); 
+        let file_ids = _query_user_files(deps.as_ref(), &user_1_permit);
+        assert_eq!(file_ids.len(), 2);
+        assert_eq!(file_ids[0], hex::encode(legacy_key));
+
+        let id_salt = ID_SALT.load(deps.as_ref().storage).unwrap();
+        let index = CONFIG.load(deps.as_ref().storage).unwrap().index;
+        assert_ne!(file_ids[1], hex::encode(Sha256::digest(index.to_le_bytes())));
+        assert_eq!(file_ids[1], hex::encode(generate_unique_id(&index, &[id_salt, [7u8; 32]].concat())));
+
+        // Without the randomness of the block, the id only depends on the secret salt
+        let mut env = mock_env();
+        env.block.random = None;
+        let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+        let msg = ExecuteMsg::Direct { execute: ExecuteMsgAction::StoreNewFile { payload: String::from("other file"), info: None } };
+        let res = execute(deps.as_mut(), env, user_1_env, msg);
+        assert!(res.is_ok());
 
-        // Query with the user the file
-        let file_content = _query_file(deps.as_ref(), user_permit, &user_file[0]);
+        let file_ids = _query_user_files(deps.as_ref(), &user_1_permit);
+        assert_eq!(file_ids[2], hex::encode(generate_unique_id(&(index + 1), &id_salt)));
 
-        // Verify that the store data is the same as the input one
-        assert_eq!(file_content, payload);
+        // Both files can be read
+        assert_eq!(_query_file(deps.as_ref(), user_1_permit.clone(), &file_ids[0]), String::from("legacy file"));
+        assert_eq!(_query_file(deps.as_ref(), user_1_permit, &file_ids[1]), String::from("new file"));
     }
- 
 
     #[test]
-    fn test_evm_store_two_same_files() {
+    fn test_delete_file() {
         let mut deps = mock_dependencies();
         setup_contract(deps.as_mut());
 
-        // Generate user information & payload
-        let (_owner, user_permit) = generate_user_1(deps.as_mut());
-        let payload = String::from("{\"file\": \"content\"}");
+        // Generate user info
+        let (user_1, user_1_permit) = generate_user_1(deps.as_mut());
+        let (user_2, user_2_permit) = generate_user_2(deps.as_mut());
 
-        let evm_message = _create_evm_message(deps.as_ref(), &payload, &user_permit);
+        // User 1 store two files and shares them with user 2
+        for payload in ["first file", "second file"] {
+            let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+            let msg = ExecuteMsg::Direct { execute: ExecuteMsgAction::StoreNewFile { payload: String::from(payload), info: None } };
+            let res = execute(deps.as_mut(), mock_env(), user_1_env, msg);
+            assert!(res.is_ok());
+        }
+        let file_ids = query_file_ids(deps.as_ref(), &mock_env().block, user_1.clone()).unwrap().file_ids;
 
-        // Send the evm message
-        let unauth_env = mock_info("anyone", &coins(0, "token"));
-        let res_store_file = execute(deps.as_mut(), mock_env(), unauth_env.clone(), evm_message.clone());
-        assert!(res_store_file.is_ok());
-        
-        // Send again the transaction 
-        let res_store_file = execute(deps.as_mut(), mock_env(), unauth_env.clone(), evm_message.clone());
-        assert!(res_store_file.is_ok());
+        for file_id in file_ids.iter() {
+            let manage_msg = ExecuteMsg::Direct { 
+                execute: ExecuteMsgAction::ManageFileRights {
+                    file_id: file_id.clone(),
+                    add_viewing: Vec::from([user_2.clone()]),
+                    delete_viewing: Vec::new(),
+                    change_owner: None,
+                    roles: Vec::new(),
+                    add_groups: Vec::new(),
+                    delete_groups: Vec::new(),
+                    rekey: false
+                }
+            };
+            let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+            let res = execute(deps.as_mut(), mock_env(), user_1_env, manage_msg);
+            assert!(res.is_ok());
+        }
 
-        // Query the user file
-        let user_file = _query_user_files(deps.as_ref(), &user_permit);
-        
-        // We should have the files and two different ids
-        assert_eq!(user_file.len(), 2);
-        assert_ne!(user_file[0], user_file[1]);
+        // Only the owner can delete a file
+        let delete_msg = |file_id: &String, tombstone: bool| ExecuteMsg::Direct { 
+            execute: ExecuteMsgAction::DeleteFile { file_id: file_id.clone(), tombstone }
+        };
+        let user_2_env = mock_info(user_2.as_str(), &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), user_2_env, delete_msg(&file_ids[0], false));
+        assert_eq!(res.unwrap_err(), ContractError::UnauthorizedAccess);
 
-        // Query with the user the file
-        let file_content_1 = _query_file(deps.as_ref(), user_permit.clone(), &user_file[0]);
-        let file_content_2 = _query_file(deps.as_ref(), user_permit.clone(), &user_file[1]);
+        // Delete the first file without a tombstone
+        let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), user_1_env, delete_msg(&file_ids[0], false));
+        assert!(res.is_ok());
 
-        // Verify that the store data is the same as the input one
-        assert_eq!(file_content_1, payload);
-        assert_eq!(file_content_2, payload);
-        assert_eq!(file_content_1, file_content_2);
-    }
+        assert_eq!(_query_user_files(deps.as_ref(), &user_1_permit), Vec::from([file_ids[1].clone()]));
+        assert_eq!(_query_user_files(deps.as_ref(), &user_2_permit), Vec::from([file_ids[1].clone()]));
+        assert!(FILE_PERMISSIONS.get(deps.as_ref().storage, &(hex::decode(&file_ids[0]).unwrap().try_into().unwrap(), user_2.clone())).is_none());
+
+        let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), user_1_env, delete_msg(&file_ids[0], false));
+        assert_eq!(res.unwrap_err(), ContractError::InvalidFileID);
+
+        // The second file is also shared with a group, put in a folder, versioned,
+        // requested and can be claimed
+        let (user_3, user_3_permit) = generate_user_3(deps.as_mut());
+        let file_key: [u8; 32] = hex::decode(&file_ids[1]).unwrap().try_into().unwrap();
+        let direct_msgs = Vec::from([
+            ExecuteMsgAction::CreateGroup { name: String::from("Board"), members: Vec::from([user_3.clone()]) },
+            ExecuteMsgAction::CreateFolder { name: String::from("Reports"), parent: None },
+        ]);
+        for msg in direct_msgs {
+            let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+            let res = execute(deps.as_mut(), mock_env(), user_1_env, ExecuteMsg::Direct { execute: msg });
+            assert!(res.is_ok());
+        }
+        let group_key = GROUPS.iter_keys(deps.as_ref().storage).unwrap().next().unwrap().unwrap();
+        let folder_key = FOLDERS.iter_keys(deps.as_ref().storage).unwrap().next().unwrap().unwrap();
+
+        let preimage = String::from("board-invitation-2024");
+        let claim_key: [u8; 32] = Sha256::digest(preimage.as_bytes()).into();
+        let direct_msgs = Vec::from([
+            ExecuteMsgAction::ManageFileRights {
+                file_id: file_ids[1].clone(),
+                add_viewing: Vec::new(),
+                delete_viewing: Vec::new(),
+                change_owner: None,
+                roles: Vec::new(),
+                add_groups: Vec::from([hex::encode(group_key)]),
+                delete_groups: Vec::new(),
+                rekey: false
+            },
+            ExecuteMsgAction::MoveFile { file_id: file_ids[1].clone(), folder_id: Some(hex::encode(folder_key)) },
+            ExecuteMsgAction::AddFileKeys { 
+                file_id: file_ids[1].clone(), 
+                keys: Vec::from([WrappedFileKey { user: user_2.clone(), wrapped_key: String::from("key for user 2") }])
+            },
+            ExecuteMsgAction::UpdateFile { file_id: file_ids[1].clone(), payload: String::from("second file, updated") },
+            ExecuteMsgAction::RegisterClaimCode { 
+                file_id: file_ids[1].clone(), 
+                claim_hash: hex::encode(claim_key),
+                max_claims: None,
+                expires_at: None
+            },
+        ]);
+        for msg in direct_msgs {
+            let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+            let res = execute(deps.as_mut(), mock_env(), user_1_env, ExecuteMsg::Direct { execute: msg });
+            assert!(res.is_ok());
+        }
+
+        let outsider_env = mock_info("outsider", &coins(0, "token"));
+        let msg = ExecuteMsg::Direct { 
+            execute: ExecuteMsgAction::RequestAccess { file_id: file_ids[1].clone(), note: String::from("Please") } 
+        };
+        let res = execute(deps.as_mut(), mock_env(), outsider_env, msg);
+        assert!(res.is_ok());
+
+        // Delete the second file with a tombstone
+        let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), user_1_env, delete_msg(&file_ids[1], true));
+        assert!(res.is_ok());
+
+        assert_eq!(_query_user_files(deps.as_ref(), &user_2_permit).len(), 0);
+        assert!(GROUPS.get(deps.as_ref().storage, &group_key).unwrap().files.is_empty());
+        assert!(FOLDERS.get(deps.as_ref().storage, &folder_key).unwrap().files.is_empty());
+        assert!(FILE_KEYS.add_suffix(&file_key).is_empty(deps.as_ref().storage));
+        assert!(FILE_VERSIONS.add_suffix(&file_key).is_empty(deps.as_ref().storage));
+        assert!(ACCESS_REQUESTS.add_suffix(&file_key).is_empty(deps.as_ref().storage));
+        assert!(PENDING_ACCESS_REQUESTS.add_suffix(&file_key).is_empty(deps.as_ref().storage));
+        assert!(CLAIM_CODES.get(deps.as_ref().storage, &claim_key).is_none());
+        assert!(FILE_CLAIM_CODES.add_suffix(&file_key).is_empty(deps.as_ref().storage));
+
+        // The former viewers through the group are told the file was deleted
+        let query_msg = QueryMsg::WithPermit { 
+            permit: user_3_permit,
+            signature_scheme: SignatureScheme::Amino,
+            query: QueryWithPermit::GetFileContent { file_id: file_ids[1].clone() },
+            public_key: None
+        };
+        let res = query(deps.as_ref(), mock_env(), query_msg);
+        assert_eq!(res.unwrap_err(), ContractError::Std(StdError::generic_err(ContractError::FileDeleted.to_string())));
+
+        // The other users cannot tell the file ever existed
+        let res = _load_visible_file(deps.as_ref(), &mock_env().block, &Vec::from([Addr::unchecked("outsider")]), &file_ids[1]);
+        assert_eq!(res.unwrap_err(), StdError::generic_err("Unauthorized access for the given file."));
+
+        let outsider_env = mock_info("outsider", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), outsider_env, delete_msg(&file_ids[1], true));
+        assert_eq!(res.unwrap_err(), ContractError::InvalidFileID);
 
+        let query_msg = QueryMsg::WithPermit { 
+            permit: user_2_permit.clone(),
+            signature_scheme: SignatureScheme::Metamask,
+            query: QueryWithPermit::GetFileContent { file_id: file_ids[1].clone() },
+            public_key: None
+        };
+        let res = query(deps.as_ref(), mock_env(), query_msg);
+        assert_eq!(res.unwrap_err(), ContractError::Std(StdError::generic_err(ContractError::FileDeleted.to_string())));
 
+        let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), user_1_env, delete_msg(&file_ids[1], true));
+        assert_eq!(res.unwrap_err(), ContractError::FileDeleted);
+    }
 
     #[test]
-    fn test_retrieve_file_from_invalid_key() {
+    fn test_file_metadata() {
         let mut deps = mock_dependencies();
         setup_contract(deps.as_mut());
 
-        let (_owner, user_permit) = generate_user_1(deps.as_mut());
-        
-        // Query with the user the file
+        // Generate user info
+        let (user_1, user_1_permit) = generate_user_1(deps.as_mut());
+        let (user_2, _) = generate_user_2(deps.as_mut());
+
+        let info = FileInfo {
+            name: Some(String::from("contract.pdf")),
+            mime_type: Some(String::from("application/pdf")),
+            size: Some(1024),
+            sha256: Some(hex::encode(Sha256::digest(b"plaintext"))),
+            tags: Vec::from([String::from("legal")]),
+        };
+
+        // The sha256 must be a digest
+        let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+        let msg = ExecuteMsg::Direct { 
+            execute: ExecuteMsgAction::StoreNewFile { 
+                payload: String::from("ipfs://content"), 
+                info: Some(FileInfo { sha256: Some(String::from("not a digest")), ..info.clone() }) 
+            } 
+        };
+        let res = execute(deps.as_mut(), mock_env(), user_1_env, msg);
+        assert!(res.is_err());
+
+        // The size of the information is bounded
+        let oversized_infos = Vec::from([
+            (
+                FileInfo { name: Some("a".repeat(MAX_FILE_NAME_LENGTH + 1)), ..info.clone() },
+                "The name cannot exceed 256 bytes"
+            ),
+            (
+                FileInfo { mime_type: Some("a".repeat(MAX_FILE_MIME_TYPE_LENGTH + 1)), ..info.clone() },
+                "The mime type cannot exceed 128 bytes"
+            ),
+            (
+                FileInfo { tags: vec![String::from("legal"); MAX_FILE_TAGS + 1], ..info.clone() },
+                "A file cannot have more than 16 tags"
+            ),
+            (
+                FileInfo { tags: Vec::from(["a".repeat(MAX_FILE_TAG_LENGTH + 1)]), ..info.clone() },
+                "A tag cannot exceed 64 bytes"
+            ),
+        ]);
+        for (oversized_info, error) in oversized_infos {
+            let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+            let msg = ExecuteMsg::Direct { 
+                execute: ExecuteMsgAction::StoreNewFile { payload: String::from("ipfs://content"), info: Some(oversized_info) } 
+            };
+            let res = execute(deps.as_mut(), mock_env(), user_1_env, msg);
+            assert_eq!(res.unwrap_err(), ContractError::CustomError { val: String::from(error) });
+        }
+
+        let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+        let msg = ExecuteMsg::Direct { 
+            execute: ExecuteMsgAction::StoreNewFile { payload: String::from("ipfs://content"), info: Some(info.clone()) } 
+        };
+        let res = execute(deps.as_mut(), mock_env(), user_1_env, msg);
+        assert!(res.is_ok());
+
+        let file_id = query_file_ids(deps.as_ref(), &mock_env().block, user_1.clone()).unwrap().file_ids[0].clone();
+
+        // The metadata is returned without the payload
         let query_msg = QueryMsg::WithPermit { 
-            permit: user_permit, 
-            query: QueryWithPermit::GetFileContent { file_id: String::from("invalid_key") } 
+            permit: user_1_permit.clone(),
+            signature_scheme: SignatureScheme::Metamask,
+            query: QueryWithPermit::GetFileMetadata { file_id: file_id.clone() },
+            public_key: None
+        };
+        let response = query(deps.as_ref(), mock_env(), query_msg.clone()).unwrap();
+        let metadata: FileMetadataResponse = from_binary(&response).unwrap();
+        assert_eq!(metadata, FileMetadataResponse {
+            owner: user_1.clone(),
+            info: info.clone(),
+            created_at: Some(mock_env().block.time),
+            updated_at: Some(mock_env().block.time),
+            version: 0,
+            pending_rekey: false,
+        });
+
+        // Only the owner can update the information
+        let new_info = FileInfo { name: Some(String::from("signed contract.pdf")), ..info.clone() };
+        let update_msg = ExecuteMsg::Direct { 
+            execute: ExecuteMsgAction::UpdateFileInfo { file_id: file_id.clone(), info: new_info.clone() }
         };
+        let user_2_env = mock_info(user_2.as_str(), &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), user_2_env, update_msg.clone());
+        assert_eq!(res.unwrap_err(), ContractError::UnauthorizedAccess);
+
+        let mut update_env = mock_env();
+        update_env.block.time = update_env.block.time.plus_seconds(10);
+        let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+        let res = execute(deps.as_mut(), update_env.clone(), user_1_env, update_msg);
+        assert!(res.is_ok());
 
-        let response = query(deps.as_ref(), mock_env(), query_msg);
-        assert!(response.is_err());
+        let response = query(deps.as_ref(), mock_env(), query_msg).unwrap();
+        let metadata: FileMetadataResponse = from_binary(&response).unwrap();
+        assert_eq!(metadata.info, new_info);
+        assert_eq!(metadata.created_at, Some(mock_env().block.time));
+        assert_eq!(metadata.updated_at, Some(update_env.block.time));
+
+        // A file id of the wrong length is rejected
+        let short_id = String::from("abcd");
+        let queries = Vec::from([
+            QueryWithPermit::GetFileContent { file_id: short_id.clone() },
+            QueryWithPermit::GetFileMetadata { file_id: short_id.clone() },
+            QueryWithPermit::GetFileVersions { file_id: short_id.clone() },
+            QueryWithPermit::GetFileVersion { file_id: short_id.clone(), version: 0 },
+            QueryWithPermit::GetFileAccess { file_id: short_id },
+        ]);
+        for query_with_permit in queries {
+            let query_msg = QueryMsg::WithPermit { 
+                permit: user_1_permit.clone(),
+                signature_scheme: SignatureScheme::Metamask,
+                query: query_with_permit,
+                public_key: None
+            };
+            let res = query(deps.as_ref(), mock_env(), query_msg);
+            assert_eq!(res.unwrap_err(), ContractError::Std(StdError::NotFound { kind: String::from("Invalid key.") }));
+        }
     }
 
-
     #[test]
-    fn test_retrieve_file_with_no_access() {
+    fn test_update_file_versions() {
         let mut deps = mock_dependencies();
         setup_contract(deps.as_mut());
 
-        // Generate user information & payload
-        let (_owner, user_permit) = generate_user_1(deps.as_mut());
-        let payload = String::from("{\"file\": \"content\"}");
-
-        let evm_message = _create_evm_message(deps.as_ref(), &payload, &user_permit);
-
-        // Send the evm message
-        let unauth_env = mock_info("anyone", &coins(0, "token"));
-        let res_store_file = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
-        assert!(res_store_file.is_ok());
-        
-        // Query the user file
-        let user_file = _query_user_files(deps.as_ref(), &user_permit);
-        
-        // We should have the file we previously stored
-        assert_eq!(user_file.len(), 1); 
+        // Generate user info
+        let (user_1, user_1_permit) = generate_user_1(deps.as_mut());
+        let (user_2, _) = generate_user_2(deps.as_mut());
+        let user_3 = Addr::unchecked("secret19qxjxm838tyez846ezm25eel7qc0dt08vnflze");
 
-        // Generate another user
-        let (_user_2, user_permit_2) = generate_user_2(deps.as_mut());
+        // User 1 store a new file, user 2 is an editor and user 3 a viewer
+        let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+        let msg = ExecuteMsg::Direct { execute: ExecuteMsgAction::StoreNewFile { payload: String::from("first version"), info: None } };
+        let res = execute(deps.as_mut(), mock_env(), user_1_env, msg);
+        assert!(res.is_ok());
 
-        // Try to get the file of the user 1
-        let query_msg = QueryMsg::WithPermit { 
-            permit: user_permit_2.clone(),
-            query: QueryWithPermit::GetFileContent { file_id: user_file[0].clone() } 
+        let file_id = query_file_ids(deps.as_ref(), &mock_env().block, user_1.clone()).unwrap().file_ids[0].clone();
+
+        let manage_msg = ExecuteMsg::Direct { 
+            execute: ExecuteMsgAction::ManageFileRights {
+                file_id: file_id.clone(),
+                add_viewing: Vec::from([user_3.clone()]),
+                delete_viewing: Vec::new(),
+                change_owner: None,
+                roles: Vec::from([RoleAssignment { address: user_2.clone(), role: FileRole::Editor, expires_at: None }]),
+                add_groups: Vec::new(),
+                delete_groups: Vec::new(),
+                rekey: false
+            }
         };
-        let response = query(deps.as_ref(), mock_env(), query_msg);
-        assert!(response.is_err());
-    }
+        let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), user_1_env, manage_msg);
+        assert!(res.is_ok());
 
+        // A viewer cannot update the file
+        let update_msg = ExecuteMsg::Direct { 
+            execute: ExecuteMsgAction::UpdateFile { file_id: file_id.clone(), payload: String::from("second version") }
+        };
+        let user_3_env = mock_info(user_3.as_str(), &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), user_3_env, update_msg.clone());
+        assert_eq!(res.unwrap_err(), ContractError::UnauthorizedAccess);
+
+        // The editor adds a new version
+        let mut update_env = mock_env();
+        update_env.block.time = update_env.block.time.plus_seconds(10);
+        let user_2_env = mock_info(user_2.as_str(), &coins(0, "token"));
+        let res = execute(deps.as_mut(), update_env.clone(), user_2_env, update_msg);
+        assert!(res.is_ok());
 
+        // The latest version is returned by default
+        let query_msg = |query: QueryWithPermit| QueryMsg::WithPermit { 
+            permit: user_1_permit.clone(),
+            signature_scheme: SignatureScheme::Metamask,
+            query,
+            public_key: None
+        };
+        let response = query(deps.as_ref(), mock_env(), query_msg(QueryWithPermit::GetFileContent { file_id: file_id.clone() })).unwrap();
+        let file_content: FilePayloadResponse = from_binary(&response).unwrap();
+        assert_eq!(file_content.payload, String::from("second version"));
+        assert_eq!(file_content.version, 1);
+
+        // List the versions
+        let response = query(deps.as_ref(), mock_env(), query_msg(QueryWithPermit::GetFileVersions { file_id: file_id.clone() })).unwrap();
+        let versions: FileVersionsResponse = from_binary(&response).unwrap();
+        assert_eq!(versions.versions, Vec::from([
+            FileVersionInfo { version: 0, author: user_1.clone(), created_at: Some(mock_env().block.time) },
+            FileVersionInfo { version: 1, author: user_2.clone(), created_at: Some(update_env.block.time) },
+        ]));
+
+        // Fetch a previous version
+        let response = query(deps.as_ref(), mock_env(), query_msg(QueryWithPermit::GetFileVersion { file_id: file_id.clone(), version: 0 })).unwrap();
+        let file_version: FileVersionResponse = from_binary(&response).unwrap();
+        assert_eq!(file_version.payload, String::from("first version"));
+        assert_eq!(file_version.author, user_1);
+
+        let res = query(deps.as_ref(), mock_env(), query_msg(QueryWithPermit::GetFileVersion { file_id, version: 2 }));
+        assert!(res.is_err());
+    }
 
     #[test]
-    fn test_store_file_and_give_user_access() {
+    fn test_rekey_file_after_revocation() {
         let mut deps = mock_dependencies();
         setup_contract(deps.as_mut());
 
         // Generate user info
-        let (user_1, user_1_permit) = generate_user_1(deps.as_mut());
+        let (user_1, _) = generate_user_1(deps.as_mut());
         let (user_2, user_2_permit) = generate_user_2(deps.as_mut());
+        let user_3 = Addr::unchecked("secret19qxjxm838tyez846ezm25eel7qc0dt08vnflze");
 
-        // Generate user information & payload
-        let payload = String::from("{\"file\": \"content\"}");
+        // User 1 store a new file and shares it with user 2 and user 3
+        let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+        let msg = ExecuteMsg::Direct { execute: ExecuteMsgAction::StoreNewFile { payload: String::from("ipfs://old_content"), info: None } };
+        let res = execute(deps.as_mut(), mock_env(), user_1_env, msg);
+        assert!(res.is_ok());
 
-        let evm_message = _create_evm_message(deps.as_ref(), &payload, &user_1_permit);
+        let file_id = query_file_ids(deps.as_ref(), &mock_env().block, user_1.clone()).unwrap().file_ids[0].clone();
+
+        let share_msg = |add_viewing: Vec<Addr>, delete_viewing: Vec<Addr>, rekey: bool| ExecuteMsg::Direct { 
+            execute: ExecuteMsgAction::ManageFileRights {
+                file_id: file_id.clone(),
+                add_viewing,
+                delete_viewing,
+                change_owner: None,
+                roles: Vec::new(),
+                add_groups: Vec::new(),
+                delete_groups: Vec::new(),
+                rekey
+            }
+        };
+        let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), user_1_env, share_msg(Vec::from([user_2.clone(), user_3.clone()]), Vec::new(), false));
+        assert!(res.is_ok());
 
-        // Send the evm message
-        let unauth_env = mock_info("anyone", &coins(0, "token"));
-        let res_store_file = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
-        assert!(res_store_file.is_ok());
+        // User 1 revokes user 3 and asks for a re-key
+        let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), user_1_env, share_msg(Vec::new(), Vec::from([user_3.clone()]), true));
+        assert!(res.is_ok());
 
-        // User 1 should have one file
-        let user_1_file = _query_user_files(deps.as_ref(), &user_1_permit);
-        assert!(!user_1_file.is_empty());
+        // The old payload is not served anymore
+        let query_msg = QueryMsg::WithPermit { 
+            permit: user_2_permit.clone(),
+            signature_scheme: SignatureScheme::Metamask,
+            query: QueryWithPermit::GetFileContent { file_id: file_id.clone() },
+            public_key: None
+        };
+        let res = query(deps.as_ref(), mock_env(), query_msg.clone());
+        assert_eq!(res.unwrap_err(), ContractError::Std(StdError::generic_err(ContractError::FilePendingRekey.to_string())));
+
+        // Only the owner can replace the payload
+        let rekey_msg = ExecuteMsg::Direct { 
+            execute: ExecuteMsgAction::RekeyFile {
+                file_id: file_id.clone(),
+                payload: String::from("ipfs://new_content"),
+                keys: Vec::from([WrappedFileKey { user: user_2.clone(), wrapped_key: String::from("new key for user 2") }])
+            }
+        };
+        let user_2_env = mock_info(user_2.as_str(), &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), user_2_env, rekey_msg.clone());
+        assert_eq!(res.unwrap_err(), ContractError::UnauthorizedAccess);
 
-        // User 2 should not have a file
-        let user_2_file = _query_user_files(deps.as_ref(), &user_2_permit);
-        assert!(user_2_file.is_empty());
+        let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), user_1_env, rekey_msg);
+        assert!(res.is_ok());
 
-        // Create request to authorize user 2
-        let evm_message = _create_manage_request_evm_message(
-            deps.as_ref(),
-            &user_1_permit,
-            user_1_file[0].clone(),
-            Vec::from([user_2.clone()]),
-            Vec::new(),
-            user_1.clone()
-        );
-        let unauth_env = mock_info("anyone", &coins(0, "token"));
-        let res_store_file = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
-        assert!(res_store_file.is_ok());
+        // User 2 sees the new payload and that the file was rotated
+        let response = query(deps.as_ref(), mock_env(), query_msg).unwrap();
+        let file_content: FilePayloadResponse = from_binary(&response).unwrap();
+        assert_eq!(file_content, FilePayloadResponse {
+            payload: String::from("ipfs://new_content"),
+            wrapped_key: Some(String::from("new key for user 2")),
+            rotated_at: Some(mock_env().block.time),
+            version: 1,
+        });
+
+        // The versions encrypted with the previous key are not served anymore
+        let query_msg = QueryMsg::WithPermit { 
+            permit: user_2_permit.clone(),
+            signature_scheme: SignatureScheme::Metamask,
+            query: QueryWithPermit::GetFileVersions { file_id: file_id.clone() },
+            public_key: None
+        };
+        let response = query(deps.as_ref(), mock_env(), query_msg).unwrap();
+        let versions: FileVersionsResponse = from_binary(&response).unwrap();
+        assert_eq!(versions.versions, Vec::from([
+            FileVersionInfo { version: 1, author: user_1.clone(), created_at: Some(mock_env().block.time) },
+        ]));
 
-        // See the list of the user 2
-        let user_2_file = _query_user_files(deps.as_ref(), &user_2_permit);
-        assert_eq!(user_2_file.len(), 1);
+        let query_msg = QueryMsg::WithPermit { 
+            permit: user_2_permit,
+            signature_scheme: SignatureScheme::Metamask,
+            query: QueryWithPermit::GetFileVersion { file_id, version: 0 },
+            public_key: None
+        };
+        let res = query(deps.as_ref(), mock_env(), query_msg);
+        assert_eq!(res.unwrap_err(), ContractError::Std(StdError::NotFound { kind: String::from("Invalid file version.") }));
+    }
 
-        // Get the file from the user 2
-        let file_content = _query_file(deps.as_ref(), user_2_permit, &user_2_file[0]);
-        
-        // Verify that the store data is the same as the input one
-        assert_eq!(file_content, payload);
+    #[test]
+    fn test_encrypted_query_response() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
 
-        // Check user 1 still have access to the file
-        let user_1_file = _query_user_files(deps.as_ref(), &user_1_permit);
-        assert_eq!(user_1_file.len(), 1);
-        let file_content = _query_file(deps.as_ref(), user_1_permit.clone(), &user_1_file[0]);
-        assert_eq!(file_content, payload);
+        // Generate user info
+        let (_user_1, user_1_permit) = generate_user_1(deps.as_mut());
+
+        let payload = String::from("{\"file\": \"content\"}");
+        let evm_message = _create_evm_message(deps.as_ref(), &payload, &user_1_permit);
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res.is_ok());
 
+        let file_id = _query_user_files(deps.as_ref(), &user_1_permit)[0].clone();
 
-        // From the owner, get the file permission
-        let file_metadata = _query_file_metadata(deps.as_ref(), user_1_permit.clone(), &user_1_file[0]);
-        assert_eq!(file_metadata.owner, user_1.clone());
-        assert_eq!(file_metadata.viewers.len(), 2);
-        assert_eq!(file_metadata.viewers[0], user_1.clone());
-        assert_eq!(file_metadata.viewers[1], user_2.clone());
+        // Query the file content with an ephemeral public key
+        let (local_public_key, local_private_key) = _generate_local_public_private_key(mock_env());
+        let query_msg = QueryMsg::WithPermit { 
+            permit: user_1_permit.clone(),
+            signature_scheme: SignatureScheme::Metamask,
+            query: QueryWithPermit::GetFileContent { file_id: file_id.clone() },
+            public_key: Some(local_public_key)
+        };
+        let response = query(deps.as_ref(), mock_env(), query_msg).unwrap();
+        let encrypted_response: EncryptedQueryResponse = from_binary(&response).unwrap();
+        assert_eq!(encrypted_response.key_version, 0);
 
-    }    
-    
+        // The payload is not readable without the shared secret
+        assert!(from_binary::<FilePayloadResponse>(&Binary::from(encrypted_response.payload.clone())).is_err());
 
+        let contract_public_key = _query_contract_pubic_key(deps.as_ref()).public_key;
+        let decrypted_response = _decrypt_with_share_secret(
+            local_private_key, 
+            contract_public_key, 
+            &encrypted_response.payload
+        );
+        let file_content: FilePayloadResponse = from_binary(&Binary::from(decrypted_response)).unwrap();
+        assert_eq!(file_content.payload, payload);
 
+        // An invalid public key is rejected
+        let query_msg = QueryMsg::WithPermit { 
+            permit: user_1_permit,
+            signature_scheme: SignatureScheme::Metamask,
+            query: QueryWithPermit::GetFileContent { file_id },
+            public_key: Some(vec![1, 2, 3])
+        };
+        assert!(query(deps.as_ref(), mock_env(), query_msg).is_err());
+    }
 
     #[test]
-    fn test_unauthorized_user_update_file_rights() {
+    fn test_rotate_contract_key() {
         let mut deps = mock_dependencies();
         setup_contract(deps.as_mut());
 
         // Generate user info
-        let (user_1, user_1_permit) = generate_user_1(deps.as_mut());
-        let (user_2, user_2_permit) = generate_user_2(deps.as_mut());
+        let (_user_1, user_1_permit) = generate_user_1(deps.as_mut());
 
-        // Generate user information & payload
-        let payload = String::from("{\"file\": \"content\"}");
+        let initial_key = _query_contract_pubic_key(deps.as_ref());
+        assert_eq!(initial_key.version, 0);
 
-        let evm_message = _create_evm_message(deps.as_ref(), &payload, &user_1_permit);
+        // Messages encrypted with the initial key
+        let first_message = _create_evm_message(deps.as_ref(), &String::from("first"), &user_1_permit);
+        let second_message = _create_evm_message(deps.as_ref(), &String::from("second"), &user_1_permit);
 
-        // Send the evm message
+        // Only the admin can rotate the key
         let unauth_env = mock_info("anyone", &coins(0, "token"));
-        let res_store_file = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
-        assert!(res_store_file.is_ok());
-
-        // Get file id
-        let file_id = &_query_user_files(deps.as_ref(), &user_1_permit)[0];
-        
-        // User 2 try to add in viewing
-        let evm_message = _create_manage_request_evm_message(
-            deps.as_ref(),
-            &user_2_permit,
-            file_id.clone(),
-            Vec::from([user_2.clone()]),
-            Vec::new(),
-            user_1.clone()
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, ExecuteMsg::RotateContractKey { grace_period: 100 });
+        assert_eq!(res.unwrap_err(), ContractError::UnauthorizedAdmin);
+
+        // The end of the grace period must be representable
+        let admin_env = mock_info("creator", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), admin_env, ExecuteMsg::RotateContractKey { grace_period: u64::MAX });
+        assert_eq!(res.unwrap_err(), ContractError::CustomError { val: String::from("The grace period is too long") });
+        assert_eq!(_query_contract_pubic_key(deps.as_ref()), initial_key);
+
+        // The randomness of the block is required
+        let mut no_random_env = mock_env();
+        no_random_env.block.random = None;
+        let admin_env = mock_info("creator", &coins(0, "token"));
+        let res = execute(deps.as_mut(), no_random_env, admin_env, ExecuteMsg::RotateContractKey { grace_period: 100 });
+        assert_eq!(
+            res.unwrap_err(), 
+            ContractError::CustomError { val: String::from("The randomness of the block is required to rotate the contract key") }
         );
-        let unauth_env = mock_info("anyone", &coins(0, "token"));
-        let res_store_file = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
-        assert!(res_store_file.is_err());
+        assert_eq!(_query_contract_pubic_key(deps.as_ref()), initial_key);
 
+        let admin_env = mock_info("creator", &coins(0, "token"));
+        let res = execute(deps.as_mut(), mock_env(), admin_env, ExecuteMsg::RotateContractKey { grace_period: 100 });
+        assert!(res.is_ok());
 
-        // User 2 try to delete user 1
-        let evm_message = _create_manage_request_evm_message(
-            deps.as_ref(),
-            &user_2_permit,
-            file_id.clone(),
-            Vec::new(),
-            Vec::from([user_2.clone()]),
-            user_1.clone()
-        );
+        let rotated_key = _query_contract_pubic_key(deps.as_ref());
+        assert_eq!(rotated_key.version, 1);
+        assert_ne!(rotated_key.public_key, initial_key.public_key);
+
+        // The previous key is still accepted during the grace period
         let unauth_env = mock_info("anyone", &coins(0, "token"));
-        let res_store_file = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
-        assert!(res_store_file.is_err());
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, first_message);
+        assert!(res.is_ok());
 
-        // User 2 try to get the owner
-        let evm_message = _create_manage_request_evm_message(
-            deps.as_ref(),
-            &user_2_permit,
-            file_id.clone(),
-            Vec::new(),
-            Vec::new(),
-            user_2.clone()
-        );
+        // The new key is accepted
+        let new_message = _create_evm_message(deps.as_ref(), &String::from("new"), &user_1_permit);
         let unauth_env = mock_info("anyone", &coins(0, "token"));
-        let res_store_file = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
-        assert!(res_store_file.is_err());
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, new_message);
+        assert!(res.is_ok());
+        assert_eq!(_query_user_files(deps.as_ref(), &user_1_permit).len(), 2);
 
+        // After the grace period, the previous key is rejected
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(101);
+        let unauth_env = mock_info("anyone", &coins(0, "token"));
+        let res = execute(deps.as_mut(), env, unauth_env, second_message);
+        assert_eq!(res.unwrap_err(), ContractError::InvalidContractKeyVersion { val: 0 });
     }
 
     #[test]
-    fn test_remove_viewing_rights_and_keep_owner() {
-        // Given a file created by an user, we want to check that if we delete the user, who owns the file 
-        // we will have an error. And the owner can still have access to the file.
-        //
-        // Note: As the transaction is in error, the transaction will be reverted. However,
-        //      here, the storage modification is not modify. This is wy in our test, at the
-        //      end we do not verify the user 2 query.
-        
+    fn test_viewing_key_queries() {
         let mut deps = mock_dependencies();
         setup_contract(deps.as_mut());
 
         // Generate user info
-        let (user_1, user_1_permit) = generate_user_1(deps.as_mut());
-        let (user_2, _user_2_permit) = generate_user_2(deps.as_mut());
+        let (user_1, _) = generate_user_1(deps.as_mut());
+        let (user_2, user_2_permit) = generate_user_2(deps.as_mut());
 
-        // Generate user information & payload
+        // User 1 store a new file
         let payload = String::from("{\"file\": \"content\"}");
+        let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+        let msg = ExecuteMsg::Direct { execute: ExecuteMsgAction::StoreNewFile { payload: payload.clone(), info: None } };
+        let res = execute(deps.as_mut(), mock_env(), user_1_env, msg);
+        assert!(res.is_ok());
 
-        let evm_message = _create_evm_message(deps.as_ref(), &payload, &user_1_permit);
+        // User 1 creates a viewing key
+        let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+        let msg = ExecuteMsg::CreateViewingKey { entropy: String::from("some entropy") };
+        let res = execute(deps.as_mut(), mock_env(), user_1_env, msg).unwrap();
+        let key = from_binary::<ViewingKeyResponse>(&res.data.unwrap()).unwrap().key;
+
+        // Query the file with the viewing key
+        let query_msg = QueryMsg::WithViewingKey { 
+            address: user_1.clone(), 
+            key: key.clone(), 
+            query: QueryWithPermit::GetFileIds {},
+            public_key: None
+        };
+        let res = query(deps.as_ref(), mock_env(), query_msg).unwrap();
+        let file_ids = from_binary::<FileIdsResponse>(&res).unwrap().file_ids;
+        assert_eq!(file_ids.len(), 1);
+
+        let query_msg = QueryMsg::WithViewingKey { 
+            address: user_1.clone(), 
+            key: key.clone(), 
+            query: QueryWithPermit::GetFileContent { file_id: file_ids[0].clone() },
+            public_key: None
+        };
+        let res = query(deps.as_ref(), mock_env(), query_msg).unwrap();
+        assert_eq!(from_binary::<FilePayloadResponse>(&res).unwrap().payload, payload);
+
+        // A wrong key is rejected
+        let query_msg = QueryMsg::WithViewingKey { 
+            address: user_1.clone(), 
+            key: String::from("wrong key"), 
+            query: QueryWithPermit::GetFileIds {},
+            public_key: None
+        };
+        assert!(query(deps.as_ref(), mock_env(), query_msg).is_err());
 
-        // Send the evm message
-        let unauth_env = mock_info("anyone", &coins(0, "token"));
-        let res_store_file = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
-        assert!(res_store_file.is_ok());
+        // User 2 sets its own key but cannot read the file of user 1
+        let user_2_env = mock_info(user_2.as_str(), &coins(0, "token"));
+        let msg = ExecuteMsg::SetViewingKey { key: String::from("user 2 key") };
+        let res = execute(deps.as_mut(), mock_env(), user_2_env, msg);
+        assert!(res.is_ok());
 
-        // Get file id
-        let file_id = &_query_user_files(deps.as_ref(), &user_1_permit)[0];
-        
-        // User 1 add user 2 access and cannot remove himself of the list as it is still the owner
-        let evm_message = _create_manage_request_evm_message(
+        let query_msg = QueryMsg::WithViewingKey { 
+            address: user_2.clone(), 
+            key: String::from("user 2 key"), 
+            query: QueryWithPermit::GetFileContent { file_id: file_ids[0].clone() },
+            public_key: None
+        };
+        assert!(query(deps.as_ref(), mock_env(), query_msg).is_err());
+
+        // The key of user 2 does not give access to the account of user 1
+        let query_msg = QueryMsg::WithViewingKey { 
+            address: user_1.clone(), 
+            key: String::from("user 2 key"), 
+            query: QueryWithPermit::GetFileIds {},
+            public_key: None
+        };
+        assert!(query(deps.as_ref(), mock_env(), query_msg).is_err());
+
+        // Once user 2 executed a permit message, the rights given to his Ethereum
+        // address apply to his viewing key
+        let evm_message = _create_action_evm_message(
             deps.as_ref(),
-            &user_1_permit,
-            file_id.clone(),
-            Vec::from([user_2.clone()]),
-            Vec::from([user_1.clone()]),
-            user_1.clone()
+            &user_2_permit,
+            ExecuteMsgAction::RevokePermit { permit_name: String::from("unused permit") }
         );
         let unauth_env = mock_info("anyone", &coins(0, "token"));
-        let res_store_file = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
-        assert!(res_store_file.is_err());
+        let res = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
+        assert!(res.is_ok());
+
+        let user_2_ethereum_address = _ethereum_address(&user_2_permit.signature.pub_key.value).unwrap();
+        let user_1_env = mock_info(user_1.as_str(), &coins(0, "token"));
+        let msg = ExecuteMsg::Direct { 
+            execute: ExecuteMsgAction::ManageFileRights {
+                file_id: file_ids[0].clone(),
+                add_viewing: Vec::from([user_2_ethereum_address]),
+                delete_viewing: Vec::new(),
+                change_owner: None,
+                roles: Vec::new(),
+                add_groups: Vec::new(),
+                delete_groups: Vec::new(),
+                rekey: false
+            }
+        };
+        let res = execute(deps.as_mut(), mock_env(), user_1_env, msg);
+        assert!(res.is_ok());
+
+        let query_msg = QueryMsg::WithViewingKey { 
+            address: user_2.clone(), 
+            key: String::from("user 2 key"), 
+            query: QueryWithPermit::GetFileContent { file_id: file_ids[0].clone() },
+            public_key: None
+        };
+        let res = query(deps.as_ref(), mock_env(), query_msg).unwrap();
+        assert_eq!(from_binary::<FilePayloadResponse>(&res).unwrap().payload, payload);
     }
 
     #[test]
-    fn test_transfert_file_ownership() {
+    fn test_migrate_legacy_contract() {
         let mut deps = mock_dependencies();
-        setup_contract(deps.as_mut());
 
-        // Generate user info
-        let (_user_1, user_1_permit) = generate_user_1(deps.as_mut());
-        let (user_2, user_2_permit) = generate_user_2(deps.as_mut());
+        // Storage written by the first version of the contract
+        let legacy_config = LegacyConfig {
+            contract_address: mock_env().contract.address,
+            index: 2,
+        };
+        LEGACY_CONFIG.save(deps.as_mut().storage, &legacy_config).unwrap();
 
-        // Generate user information & payload
-        let payload = String::from("{\"file\": \"content\"}");
+        let legacy_keys = LegacyContractKeys {
+            private_key: vec![1; 32],
+            public_key: vec![2; 33],
+        };
+        LEGACY_CONTRACT_KEYS.save(deps.as_mut().storage, &legacy_keys).unwrap();
 
-        let evm_message = _create_evm_message(deps.as_ref(), &payload, &user_1_permit);
+        let owner = Addr::unchecked("secret1owner");
+        let viewer = Addr::unchecked("secret1viewer");
+        let file_key = generate_unique_id(&1, &[]);
 
-        // Send the evm message
-        let unauth_env = mock_info("anyone", &coins(0, "token"));
-        let res_store_file = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
-        assert!(res_store_file.is_ok());
+        let mut file_storage = PrefixedStorage::new(deps.as_mut().storage, PREFIX_FILES);
+        file_storage.set(&file_key, b"{\"payload\":\"legacy content\"}");
 
-        // User 1 should have one file
-        let user_1_file = _query_user_files(deps.as_ref(), &user_1_permit);
-        assert!(!user_1_file.is_empty());
+        let mut file_metadata_storage = PrefixedStorage::new(deps.as_mut().storage, PREFIX_FILES_METADATA);
+        file_metadata_storage.set(&file_key, b"{\"owner\":\"secret1owner\",\"viewers\":[\"secret1owner\",\"secret1viewer\"]}");
 
-        // User 2 should not have a file
-        let user_2_file = _query_user_files(deps.as_ref(), &user_2_permit);
-        assert!(user_2_file.is_empty());
-        
-        // Create request to authorize user 2
-        let updated_user_id = user_1_file[0].clone();
-        let evm_message = _create_manage_request_evm_message(
-            deps.as_ref(),
-            &user_1_permit,
-            user_1_file[0].clone(),
-            Vec::new(),
-            Vec::new(),
-            user_2
-        );
-        let unauth_env = mock_info("anyone", &coins(0, "token"));
-        let res_store_file = execute(deps.as_mut(), mock_env(), unauth_env, evm_message);
-        assert!(res_store_file.is_ok());
+        let mut users_store = PrefixedStorage::new(deps.as_mut().storage, PREFIX_USERS);
+        save(&mut users_store, owner.as_bytes(), &UserInfo { files: Vec::from([file_key]) }).unwrap();
+        save(&mut users_store, viewer.as_bytes(), &UserInfo { files: Vec::from([file_key]) }).unwrap();
 
-        // User 1 should still have access to the file
-        let user_1_file = _query_user_files(deps.as_ref(), &user_1_permit);
-        assert!(!user_1_file.is_empty());
-        assert_eq!(user_1_file[0], updated_user_id);
+        LEGACY_FILE_PERMISSIONS.insert(deps.as_mut().storage, &(file_key, owner.clone()), &true).unwrap();
+        LEGACY_FILE_PERMISSIONS.insert(deps.as_mut().storage, &(file_key, viewer.clone()), &true).unwrap();
+        LEGACY_FILE_PERMISSIONS.insert(deps.as_mut().storage, &(file_key, Addr::unchecked("secret1removed")), &false).unwrap();
 
-        // User 2 should now have access to the file
-        let user_2_file = _query_user_files(deps.as_ref(), &user_2_permit);
-        assert!(!user_2_file.is_empty());
-        assert_eq!(user_2_file[0], updated_user_id);
+        // The first version had no admin, one must be given
+        let res = migrate(deps.as_mut(), mock_env(), MigrateMsg { admin: None, limit: None });
+        assert_eq!(res.unwrap_err(), ContractError::CustomError { val: String::from("An admin is required to migrate the contract") });
 
-    
-    }
+        let mut env = mock_env();
+        env.block.random = None;
+        let res = migrate(deps.as_mut(), env, MigrateMsg { admin: Some(Addr::unchecked("new_admin")), limit: None });
+        assert_eq!(res.unwrap_err(), ContractError::CustomError { val: String::from("The randomness of the block is required to migrate the contract") });
 
+        // The accesses can be converted in several calls, the contract is paused meanwhile
+        let res = migrate(deps.as_mut(), mock_env(), MigrateMsg { admin: Some(Addr::unchecked("new_admin")), limit: Some(2) });
+        assert!(res.is_ok());
+
+        let config = CONFIG.load(deps.as_ref().storage).unwrap();
+        assert!(config.writes_paused && config.reads_paused);
+        assert_eq!(STORAGE_VERSION.may_load(deps.as_ref().storage).unwrap(), None);
+        assert_eq!(LEGACY_PERMISSIONS_LEFT.load(deps.as_ref().storage).unwrap(), 1);
+        assert_eq!(FILE_PERMISSIONS.get_len(deps.as_ref().storage).unwrap(), 2);
+        assert_eq!(LEGACY_FILE_PERMISSIONS.get(deps.as_ref().storage, &(file_key, owner.clone())), Some(true));
+
+        let res = migrate(deps.as_mut(), mock_env(), MigrateMsg { admin: None, limit: Some(2) });
+        assert!(res.is_ok());
+        assert_eq!(LEGACY_PERMISSIONS_LEFT.may_load(deps.as_ref().storage).unwrap(), None);
+
+        let config = CONFIG.load(deps.as_ref().storage).unwrap();
+        assert_eq!(config, Config {
+            contract_address: mock_env().contract.address,
+            index: 2,
+            admin: Addr::unchecked("new_admin"),
+            writes_paused: false,
+            reads_paused: false,
+        });
+        assert_eq!(STORAGE_VERSION.load(deps.as_ref().storage).unwrap(), CURRENT_STORAGE_VERSION);
+
+        // The keys of the first version are the version 0
+        assert_eq!(CONTRACT_KEYS.load(deps.as_ref().storage).unwrap(), ContractKeys {
+            private_key: vec![1; 32],
+            public_key: vec![2; 33],
+            version: 0,
+        });
+
+        // The boolean accesses are now roles
+        let block = mock_env().block;
+        assert_eq!(get_file_role(deps.as_ref().storage, &block, file_key, &owner), Some(FileRole::Owner));
+        assert_eq!(get_file_role(deps.as_ref().storage, &block, file_key, &viewer), Some(FileRole::Viewer));
+
+        // The legacy file can still be read by its owner
+        let owner_env = mock_info(owner.as_str(), &coins(0, "token"));
+        let msg = ExecuteMsg::SetViewingKey { key: String::from("owner key") };
+        let res = execute(deps.as_mut(), mock_env(), owner_env, msg);
+        assert!(res.is_ok());
+
+        let query_msg = QueryMsg::WithViewingKey { 
+            address: owner.clone(), 
+            key: String::from("owner key"), 
+            query: QueryWithPermit::GetFileContent { file_id: hex::encode(file_key) },
+            public_key: None
+        };
+        let res = query(deps.as_ref(), mock_env(), query_msg).unwrap();
+        let file_content: FilePayloadResponse = from_binary(&res).unwrap();
+        assert_eq!(file_content.payload, String::from("legacy content"));
+        assert_eq!(file_content.version, 0);
+
+        // The payload of the legacy file is its first version, kept after an update
+        let owner_env = mock_info(owner.as_str(), &coins(0, "token"));
+        let msg = ExecuteMsg::Direct { execute: ExecuteMsgAction::UpdateFile { file_id: hex::encode(file_key), payload: String::from("updated content") } };
+        let res = execute(deps.as_mut(), mock_env(), owner_env, msg);
+        assert!(res.is_ok());
 
+        let query_msg = QueryMsg::WithViewingKey { 
+            address: owner.clone(), 
+            key: String::from("owner key"), 
+            query: QueryWithPermit::GetFileVersions { file_id: hex::encode(file_key) },
+            public_key: None
+        };
+        let res = query(deps.as_ref(), mock_env(), query_msg).unwrap();
+        let versions: FileVersionsResponse = from_binary(&res).unwrap();
+        assert_eq!(versions.versions, Vec::from([
+            FileVersionInfo { version: 0, author: owner.clone(), created_at: None },
+            FileVersionInfo { version: 1, author: owner.clone(), created_at: Some(mock_env().block.time) },
+        ]));
+
+        let query_msg = QueryMsg::WithViewingKey { 
+            address: owner.clone(), 
+            key: String::from("owner key"), 
+            query: QueryWithPermit::GetFileVersion { file_id: hex::encode(file_key), version: 0 },
+            public_key: None
+        };
+        let res = query(deps.as_ref(), mock_env(), query_msg).unwrap();
+        let file_version: FileVersionResponse = from_binary(&res).unwrap();
+        assert_eq!(file_version.payload, String::from("legacy content"));
+
+        let query_msg = QueryMsg::WithViewingKey { 
+            address: owner.clone(), 
+            key: String::from("owner key"), 
+            query: QueryWithPermit::GetFileMetadata { file_id: hex::encode(file_key) },
+            public_key: None
+        };
+        let res = query(deps.as_ref(), mock_env(), query_msg).unwrap();
+        let file_metadata: FileMetadataResponse = from_binary(&res).unwrap();
+        assert_eq!(file_metadata.owner, owner);
+        assert_eq!(file_metadata.info, FileInfo::default());
+        assert_eq!(file_metadata.created_at, None);
+
+        // The new files get a salted id after the counter of the first version
+        let id_salt = ID_SALT.load(deps.as_ref().storage).unwrap();
+        let owner_env = mock_info(owner.as_str(), &coins(0, "token"));
+        let msg = ExecuteMsg::Direct { execute: ExecuteMsgAction::StoreNewFile { payload: String::from("new content"), info: None } };
+        let res = execute(deps.as_mut(), mock_env(), owner_env, msg);
+        assert!(res.is_ok());
+
+        let random = mock_env().block.random.unwrap();
+        let new_file_key = generate_unique_id(&3, &[id_salt.as_slice(), random.as_slice()].concat());
+        let user_store = ReadonlyPrefixedStorage::new(deps.as_ref().storage, PREFIX_USERS);
+        let user_info: UserInfo = load(&user_store, owner.as_bytes()).unwrap();
+        assert_eq!(user_info.files, Vec::from([file_key, new_file_key]));
+        assert_eq!(get_file_role(deps.as_ref().storage, &block, new_file_key, &owner), Some(FileRole::Owner));
+
+        // Migrating a contract already up to date changes nothing
+        let config = CONFIG.load(deps.as_ref().storage).unwrap();
+        let res = migrate(deps.as_mut(), mock_env(), MigrateMsg { admin: None, limit: None });
+        assert!(res.is_ok());
+        assert_eq!(CONFIG.load(deps.as_ref().storage).unwrap(), config);
+    }
 
     #[test]
     fn test_verify_permit_from_metamask() {
@@ -1402,7 +6583,7 @@ mod tests {
                 allowed_tokens: vec![String::from(&contract_address)],
                 permit_name: permit_name.to_string(),
                 chain_id: chain_id.to_string(),
-                permissions: vec![TokenPermissions::Owner],
+                permissions: vec![DocumentPermission::Owner],
             },
             signature: PermitSignature {
                 pub_key: PubKey {
@@ -1413,7 +6594,7 @@ mod tests {
             }
         };
 
-        let data = _verify_permit(deps.as_ref(), user_permit, contract_address);
+        let data = _verify_permit(deps.as_ref(), user_permit, SignatureScheme::Metamask, contract_address, &mock_env().block.chain_id);
         
         assert!(data.is_ok());
         assert!(data.unwrap() == user_address);
@@ -35,4 +35,66 @@ pub enum ContractError {
     #[error("Invalid file id. The file does not seems to exists.")]
     InvalidFileID,
 
+    #[error("The owner of a file can only be changed through an ownership transfer.")]
+    OwnershipTransferRequired,
+
+    #[error("No ownership transfer is pending for the given file and user.")]
+    NoPendingOwnershipTransfer,
+
+    #[error("No access request is pending for the given file and user.")]
+    NoPendingAccessRequest,
+
+    #[error("Invalid claim code. The code does not exist, has expired or has already been claimed.")]
+    InvalidClaimCode,
+
+    #[error("Invalid group id. The group does not seems to exists.")]
+    InvalidGroupID,
+
+    // issued when message sender != group owner, or when sharing a file with a group of other users
+    #[error("Unauthorized access for the given group.")]
+    UnauthorizedGroupAccess,
+
+    #[error("Invalid folder id. The folder does not seems to exists.")]
+    InvalidFolderID,
+
+    // issued when message sender != folder owner
+    #[error("Unauthorized access for the given folder.")]
+    UnauthorizedFolderAccess,
+
+    // issued when message sender != contract admin
+    #[error("Only the admin of the contract can call this function.")]
+    UnauthorizedAdmin,
+
+    #[error("The contract is paused.")]
+    ContractPaused,
+
+    // issued when the relayer or the source of the message is not allowed
+    #[error("The origin of the message is not allowed.")]
+    UnauthorizedOrigin,
+
+    #[error("This message has already been processed.")]
+    MessageAlreadyProcessed,
+
+    #[error("The deadline of the message has passed.")]
+    MessageExpired,
+
+    #[error("The permit does not have the required permission: {val:?}")]
+    MissingPermitPermission { val: String },
+
+    // issued when the key version is unknown or its grace period has ended
+    #[error("Invalid contract key version: {val:?}")]
+    InvalidContractKeyVersion { val: u32 },
+
+    // issued when reading a file revoked with a re-key, before the owner replaces it
+    #[error("The file is waiting for a new key from its owner.")]
+    FilePendingRekey,
+
+    #[error("The file has been deleted by its owner.")]
+    FileDeleted,
+
+    // issued for an unknown file, a user who already has access or a denied request,
+    // so the ids of the files cannot be probed
+    #[error("The access to the given file cannot be requested.")]
+    InvalidAccessRequest,
+
 }
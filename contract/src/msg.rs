@@ -1,13 +1,26 @@
-use cosmwasm_std::{Addr, Binary};
+use cosmwasm_std::{Addr, Binary, Timestamp};
 
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use secret_toolkit::permit::Permit;
 
+use crate::state::{AccessRequestStatus, Expiration, FileInfo, FileRole};
+
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct InstantiateMsg {
+    #[serde(default)]
+    pub admin: Option<Addr>, // Default to the sender of the message
+}
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
-pub struct InstantiateMsg {}
+pub struct MigrateMsg {
+    #[serde(default)]
+    pub admin: Option<Addr>, // Required to migrate a contract without admin
+    #[serde(default)]
+    pub limit: Option<u32>, // Maximum number of legacy accesses converted, all of them by default
+}
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
@@ -21,7 +34,50 @@ pub enum ExecuteMsg {
         source_chain: String,
         source_address: String,
         payload: EncryptedExecuteMsg,
-    }
+    },
+    PauseWrites { // Only admin of the contract can call with this request
+        paused: bool,
+    },
+    PauseReads { // Only admin of the contract can call with this request
+        paused: bool,
+    },
+    ChangeAdmin { // Only admin of the contract can call with this request
+        new_admin: Addr,
+    },
+    UpdateAllowedSources { // Only admin of the contract can call with this request
+        add: Vec<AllowedSource>,
+        remove: Vec<AllowedSource>,
+    },
+    UpdateAllowedRelayers { // Only admin of the contract can call with this request
+        add: Vec<Addr>,
+        remove: Vec<Addr>,
+    },
+    RotateContractKey { // Only admin of the contract can call with this request
+        grace_period: u64, // Seconds during which the previous key is still accepted
+    },
+    PruneProcessedMessages { // Only admin of the contract can call with this request
+        start_page: u32,
+        page_size: u32, // Number of processed messages checked
+    },
+    RevokePermit { // Revoke a permit of the sender of the message
+        permit_name: String,
+    },
+    Direct { // Execute an action for the sender of the message, without Axelar
+        execute: ExecuteMsgAction,
+    },
+    CreateViewingKey { // Create a random viewing key for the sender of the message
+        entropy: String,
+    },
+    SetViewingKey { // Set the viewing key of the sender of the message
+        key: String,
+    },
+}
+
+/// Contract allowed to send messages through Axelar GMP.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct AllowedSource {
+    pub source_chain: String,
+    pub source_address: String,
 }
 
 
@@ -30,6 +86,8 @@ pub enum ExecuteMsg {
 pub struct EncryptedExecuteMsg {
     pub payload: Vec<u8>,
     pub public_key: Vec<u8>,
+    #[serde(default)]
+    pub key_version: u32, // Version of the contract key used to encrypt the payload
 }
 
 
@@ -38,8 +96,12 @@ pub struct EncryptedExecuteMsg {
 #[serde(rename_all = "snake_case")]
 pub enum ExecutePermitMsg {
     WithPermit {
-        permit: Permit,
+        permit: Permit<DocumentPermission>,
+        #[serde(default)]
+        signature_scheme: SignatureScheme,
         execute: ExecuteMsgAction,
+        #[serde(default)]
+        deadline: Option<Expiration>, // The message is rejected after this block height or time
     },
 }
 
@@ -49,22 +111,177 @@ pub enum ExecutePermitMsg {
 pub enum ExecuteMsgAction {
     StoreNewFile {
         payload: String,
+        #[serde(default)]
+        info: Option<FileInfo>, // Name, type... of the file, readable without the payload
     },
     ManageFileRights { // Only owner of the file can call with this request
         file_id: String,
         add_viewing: Vec<Addr>, // Add viewing rights
         delete_viewing: Vec<Addr>,  // Delete viewing rights
-        change_owner: Addr,  // Change owner of the file
-    }
+        #[serde(default)]
+        change_owner: Option<Addr>,  // Must be the current owner, see ProposeOwnershipTransfer
+        #[serde(default)]
+        roles: Vec<RoleAssignment>, // Set the role of some users
+        #[serde(default)]
+        add_groups: Vec<String>, // Add viewing rights to groups
+        #[serde(default)]
+        delete_groups: Vec<String>, // Delete viewing rights of groups
+        #[serde(default)]
+        rekey: bool, // Stop serving the payload until the owner replaces it, see RekeyFile
+    },
+    ProposeOwnershipTransfer { // Only owner of the file can call with this request
+        file_id: String,
+        new_owner: Addr,
+    },
+    AcceptOwnership { // Only the proposed new owner can call with this request
+        file_id: String,
+    },
+    CancelOwnershipTransfer { // Only owner of the file can call with this request
+        file_id: String,
+    },
+    RequestAccess {
+        file_id: String,
+        note: String,
+    },
+    ApproveRequest { // Only owner or manager of the file can call with this request
+        file_id: String,
+        requester: Addr,
+    },
+    DenyRequest { // Only owner or manager of the file can call with this request
+        file_id: String,
+        requester: Addr,
+    },
+    RegisterClaimCode { // Only owner or manager of the file can call with this request
+        file_id: String,
+        claim_hash: String, // Hex encoded sha256 of the preimage
+        #[serde(default)]
+        max_claims: Option<u32>,
+        #[serde(default)]
+        expires_at: Option<Expiration>,
+    },
+    RevokeClaimCode { // Only owner or manager of the file can call with this request
+        claim_hash: String,
+    },
+    ClaimAccess {
+        preimage: String,
+    },
+    CreateGroup {
+        name: String,
+        members: Vec<Addr>,
+    },
+    UpdateGroupMembers { // Only owner of the group can call with this request
+        group_id: String,
+        add_members: Vec<Addr>,
+        remove_members: Vec<Addr>,
+    },
+    CreateFolder {
+        name: String,
+        #[serde(default)]
+        parent: Option<String>, // Only owner of the parent folder can create a sub-folder
+    },
+    ManageFolderRights { // Only owner of the folder can call with this request
+        folder_id: String,
+        add_viewing: Vec<Addr>,
+        delete_viewing: Vec<Addr>,
+    },
+    MoveFile { // Only owner or manager of the file, and owner of the destination folder
+        file_id: String,
+        #[serde(default)]
+        folder_id: Option<String>, // None to remove the file from its folder
+    },
+    MoveFolder { // Only owner of the folder and of the destination folder
+        folder_id: String,
+        #[serde(default)]
+        parent: Option<String>, // None to make it a root folder
+    },
+    RevokePermit {
+        permit_name: String,
+    },
+    AddFileKeys { // Only owner or manager of the file can call with this request
+        file_id: String,
+        keys: Vec<WrappedFileKey>, // Key of the file wrapped for each user
+    },
+    DeleteFile { // Only owner of the file can call with this request
+        file_id: String,
+        #[serde(default)]
+        tombstone: bool, // Answer a FileDeleted error instead of an invalid file id
+    },
+    UpdateFileInfo { // Only owner of the file can call with this request
+        file_id: String,
+        info: FileInfo,
+    },
+    UpdateFile { // Only owner or editor of the file can call with this request
+        file_id: String,
+        payload: String, // Content of the new version
+    },
+    RekeyFile { // Only owner of the file can call with this request
+        file_id: String,
+        payload: String, // New payload, encrypted with a new key
+        #[serde(default)]
+        keys: Vec<WrappedFileKey>, // New key wrapped for each remaining user
+    },
+}
+
+/// Key of a file wrapped (encrypted) for a user having access to the file.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct WrappedFileKey {
+    pub user: Addr,
+    pub wrapped_key: String,
+}
+
+/// Scheme used to sign a permit.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SignatureScheme {
+    #[default]
+    Metamask, // Ethereum personal sign over the pretty amino JSON
+    Amino,    // Keplr and Cosmos wallets, sha256 over the sorted amino JSON
+    Eip712,   // Ethereum typed data, readable by the user in his wallet
+}
+
+/// Permissions of a permit. Each query and action requires one of them.
+///
+/// Deleting a file cannot be undone, so it is not given by any narrow permission
+/// and requires the `Owner` one.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DocumentPermission {
+    List,     // List the files, groups, folders... of the user
+    Read,     // Read the content of the files
+    Write,    // Store new files, request or claim an access
+    Manage,   // Manage the rights of the files, groups and folders
+    Transfer, // Transfer the ownership of the files
+    Owner,    // All the permissions
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct RoleAssignment {
+    pub address: Addr,
+    pub role: FileRole,
+    #[serde(default)]
+    pub expires_at: Option<Expiration>, // The access is removed after this block height or time
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum QueryMsg {
     GetContractKey {},
+    GetContractStatus {},
+    GetAllowedOrigins {},
     WithPermit {
-        permit: Permit,
+        permit: Permit<DocumentPermission>,
+        #[serde(default)]
+        signature_scheme: SignatureScheme,
+        query: QueryWithPermit,
+        #[serde(default)]
+        public_key: Option<Vec<u8>>, // Ephemeral key used to encrypt the response
+    },
+    WithViewingKey {
+        address: Addr,
+        key: String,
         query: QueryWithPermit,
+        #[serde(default)]
+        public_key: Option<Vec<u8>>, // Ephemeral key used to encrypt the response
     },
 }
 
@@ -73,18 +290,75 @@ pub enum QueryMsg {
 pub enum QueryWithPermit {
     GetFileIds {},
     GetFileContent { file_id: String },
+    GetFileVersions { file_id: String },
+    GetFileVersion { file_id: String, version: u32 },
+    GetFileMetadata { file_id: String },
     GetFileAccess { file_id: String },
+    GetAccessRequests {},
+    GetAccessRequestStatus { file_id: String },
+    GetGroupIds {},
+    GetGroup { group_id: String },
+    GetFolderIds {},
+    GetFolder { folder_id: String },
+    GetRevokedPermits {},
 }
 
 // We define a custom struct for each query response
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
 pub struct FilePayloadResponse {
     pub payload: String,
+    pub wrapped_key: Option<String>, // Key of the file wrapped for the user, if any
+    pub rotated_at: Option<Timestamp>, // Last time the file was re-keyed, if any
+    pub version: u32, // Latest version of the file
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct FileMetadataResponse {
+    pub owner: Addr,
+    pub info: FileInfo,
+    pub created_at: Option<Timestamp>,
+    pub updated_at: Option<Timestamp>,
+    pub version: u32,
+    pub pending_rekey: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct FileVersionsResponse {
+    pub versions: Vec<FileVersionInfo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct FileVersionInfo {
+    pub version: u32,
+    pub author: Addr,
+    pub created_at: Option<Timestamp>, // Unknown for a file stored before the versioning
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct FileVersionResponse {
+    pub version: u32,
+    pub author: Addr,
+    pub created_at: Option<Timestamp>, // Unknown for a file stored before the versioning
+    pub payload: String,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
 pub struct ContractKeyResponse {
     pub public_key: Vec<u8>, 
+    pub version: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct ContractStatusResponse {
+    pub admin: Addr,
+    pub writes_paused: bool,
+    pub reads_paused: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct AllowedOriginsResponse {
+    pub sources: Vec<AllowedSource>,
+    pub relayers: Vec<Addr>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
@@ -96,4 +370,67 @@ pub struct FileIdsResponse {
 pub struct FileAccessResponse {
     pub owner: Addr,
     pub viewers: Vec<Addr>,
+    pub roles: Vec<RoleAssignment>,
+    pub groups: Vec<String>,
+    pub pending_owner: Option<Addr>,
+    pub pending_rekey: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct AccessRequestResponse {
+    pub file_id: String,
+    pub requester: Addr,
+    pub note: String,
+    pub status: AccessRequestStatus,
+    pub requested_at: Timestamp,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct AccessRequestsResponse {
+    pub requests: Vec<AccessRequestResponse>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct GroupIdsResponse {
+    pub group_ids: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct GroupResponse {
+    pub name: String,
+    pub owner: Addr,
+    pub members: Vec<Addr>,
+    pub file_ids: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct RevokedPermitsResponse {
+    pub permit_names: Vec<String>,
+}
+
+/// Response encrypted for the ephemeral public key of the user.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct EncryptedQueryResponse {
+    pub payload: Vec<u8>,
+    pub key_version: u32, // Version of the contract key used to create the shared secret
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct ViewingKeyResponse {
+    pub key: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct FolderIdsResponse {
+    pub folder_ids: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct FolderResponse {
+    pub name: String,
+    pub owner: Addr,
+    pub viewers: Vec<Addr>,
+    pub parent: Option<String>,
+    pub file_ids: Vec<String>,
+    pub folder_ids: Vec<String>,
 }
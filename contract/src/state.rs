@@ -4,7 +4,7 @@ use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde::de::DeserializeOwned;
 
-use cosmwasm_std::{Addr, StdError, StdResult, Storage};
+use cosmwasm_std::{Addr, BlockInfo, StdError, StdResult, Storage, Timestamp};
 
 use secret_toolkit::serialization::{Json, Serde};
 use secret_toolkit::storage::{Item, Keymap};
@@ -12,7 +12,51 @@ use secret_toolkit::storage::{Item, Keymap};
 
 pub const KEY_CONFIG: &[u8] = b"config";
 pub const KEY_CONTRACT_KEYS: &[u8] = b"contract_keys";
+pub const KEY_PREVIOUS_CONTRACT_KEYS: &[u8] = b"previous_contract_keys";
 pub const KEY_FILE_PERMISSIONS: &[u8] = b"files_permissions";
+pub const KEY_FILE_KEYS: &[u8] = b"files_keys";
+pub const KEY_FILE_VERSIONS: &[u8] = b"files_versions";
+pub const KEY_DELETED_FILES: &[u8] = b"deleted_files";
+pub const KEY_DELETED_FILE_VIEWERS: &[u8] = b"deleted_files_viewers";
+pub const KEY_GROUPS: &[u8] = b"groups";
+pub const KEY_USER_GROUPS: &[u8] = b"users_groups";
+pub const KEY_PENDING_OWNERS: &[u8] = b"pending_owners";
+pub const KEY_ACCESS_REQUESTS: &[u8] = b"access_requests";
+pub const KEY_PENDING_ACCESS_REQUESTS: &[u8] = b"pending_access_requests";
+pub const KEY_CLAIM_CODES: &[u8] = b"claim_codes";
+pub const KEY_FILE_CLAIM_CODES: &[u8] = b"files_claim_codes";
+pub const KEY_FOLDERS: &[u8] = b"folders";
+pub const KEY_USER_FOLDERS: &[u8] = b"users_folders";
+pub const KEY_ALLOWED_SOURCES: &[u8] = b"allowed_sources";
+pub const KEY_ALLOWED_RELAYERS: &[u8] = b"allowed_relayers";
+pub const KEY_PROCESSED_MESSAGES: &[u8] = b"processed_messages";
+pub const KEY_USER_REVOKED_PERMITS: &[u8] = b"users_revoked_permits";
+pub const KEY_ETHEREUM_ADDRESSES: &[u8] = b"ethereum_addresses";
+pub const KEY_STORAGE_VERSION: &[u8] = b"storage_version";
+pub const KEY_ID_SALT: &[u8] = b"id_salt";
+pub const KEY_LEGACY_PERMISSIONS_LEFT: &[u8] = b"legacy_permissions_left";
+
+/// Version of the storage written by this code, see `migrate`
+pub const CURRENT_STORAGE_VERSION: u32 = 1;
+
+/// Limits of the information of a file, loaded with its metadata on each access
+pub const MAX_FILE_NAME_LENGTH: usize = 256;
+pub const MAX_FILE_MIME_TYPE_LENGTH: usize = 128;
+pub const MAX_FILE_TAGS: usize = 16;
+pub const MAX_FILE_TAG_LENGTH: usize = 64;
+
+/// Limit of the note of an access request, loaded with the requests of the owner
+pub const MAX_ACCESS_REQUEST_NOTE_LENGTH: usize = 256;
+
+/// Limits of a group, loaded with its members on each access through the group
+pub const MAX_GROUP_NAME_LENGTH: usize = 128;
+pub const MAX_GROUP_MEMBERS: usize = 256;
+
+/// Limits of the folders, whose parents are walked through on each access through a folder
+pub const MAX_FOLDER_NAME_LENGTH: usize = 128;
+pub const MAX_FOLDER_DEPTH: usize = 8;
+pub const MAX_FOLDER_FILES: usize = 256;
+pub const MAX_FOLDER_SUB_FOLDERS: usize = 64;
 
 /// Prefix to store all the files in the smart contract
 pub const PREFIX_FILES: &[u8] = b"files";
@@ -22,11 +66,86 @@ pub const PREFIX_USERS: &[u8] = b"users";
 
 pub static CONFIG: Item<Config> = Item::new(KEY_CONFIG);
 
+/// Configuration stored by the first version of the contract, read by the migration
+pub static LEGACY_CONFIG: Item<LegacyConfig> = Item::new(KEY_CONFIG);
+
+/// Secret salt of the ids of the files, groups and folders
+pub static ID_SALT: Item<[u8; 32]> = Item::new(KEY_ID_SALT);
+
+/// Version of the storage, missing for a contract instantiated by the first version
+pub static STORAGE_VERSION: Item<u32> = Item::new(KEY_STORAGE_VERSION);
+
+/// Number of boolean accesses still to convert, while the migration is in progress
+pub static LEGACY_PERMISSIONS_LEFT: Item<u32> = Item::new(KEY_LEGACY_PERMISSIONS_LEFT);
+
 /// Item to store the public/private key of the Secret Smart Contract
 pub static CONTRACT_KEYS: Item<ContractKeys> = Item::new(KEY_CONTRACT_KEYS);
 
-/// (file_id, user_address) => access
-pub static FILE_PERMISSIONS: Keymap<([u8; 32], Addr), bool> = Keymap::new(KEY_FILE_PERMISSIONS);
+/// Keys stored by the first version of the contract, read by the migration
+pub static LEGACY_CONTRACT_KEYS: Item<LegacyContractKeys> = Item::new(KEY_CONTRACT_KEYS);
+
+/// key version => previous keys of the contract, still accepted during the grace period
+pub static PREVIOUS_CONTRACT_KEYS: Keymap<u32, PreviousContractKeys> = Keymap::new(KEY_PREVIOUS_CONTRACT_KEYS);
+
+/// (file_id, user_address) => permission of the user on the file
+pub static FILE_PERMISSIONS: Keymap<([u8; 32], Addr), FilePermission> = Keymap::new(KEY_FILE_PERMISSIONS);
+
+/// (file_id, user_address) => access, stored by the first version of the contract
+pub static LEGACY_FILE_PERMISSIONS: Keymap<([u8; 32], Addr), bool> = Keymap::new(KEY_FILE_PERMISSIONS);
+
+/// user_address => key of the file content wrapped for the user, suffixed by the file_id
+pub static FILE_KEYS: Keymap<Addr, String> = Keymap::new(KEY_FILE_KEYS);
+
+/// version => version of the file content, suffixed by the file_id
+pub static FILE_VERSIONS: Keymap<u32, FileVersion> = Keymap::new(KEY_FILE_VERSIONS);
+
+/// file_id => deletion time, when the owner left a tombstone
+pub static DELETED_FILES: Keymap<[u8; 32], Timestamp> = Keymap::new(KEY_DELETED_FILES);
+
+/// user_address => had access to the file when it was deleted with a tombstone, suffixed by the file_id
+pub static DELETED_FILE_VIEWERS: Keymap<Addr, bool> = Keymap::new(KEY_DELETED_FILE_VIEWERS);
+
+/// group_id => group
+pub static GROUPS: Keymap<[u8; 32], Group> = Keymap::new(KEY_GROUPS);
+
+/// user_address => ids of the groups the user is a member of
+pub static USER_GROUPS: Keymap<Addr, Vec<[u8; 32]>> = Keymap::new(KEY_USER_GROUPS);
+
+/// file_id => proposed new owner, waiting for the acceptation
+pub static PENDING_OWNERS: Keymap<[u8; 32], Addr> = Keymap::new(KEY_PENDING_OWNERS);
+
+/// requester => access request, suffixed by the file_id
+pub static ACCESS_REQUESTS: Keymap<Addr, AccessRequest> = Keymap::new(KEY_ACCESS_REQUESTS);
+
+/// requester => request not answered yet, suffixed by the file_id
+pub static PENDING_ACCESS_REQUESTS: Keymap<Addr, bool> = Keymap::new(KEY_PENDING_ACCESS_REQUESTS);
+
+/// sha256(preimage) => claim code
+pub static CLAIM_CODES: Keymap<[u8; 32], ClaimCode> = Keymap::new(KEY_CLAIM_CODES);
+
+/// sha256(preimage) => registered, suffixed by the file_id
+pub static FILE_CLAIM_CODES: Keymap<[u8; 32], bool> = Keymap::new(KEY_FILE_CLAIM_CODES);
+
+/// folder_id => folder
+pub static FOLDERS: Keymap<[u8; 32], Folder> = Keymap::new(KEY_FOLDERS);
+
+/// user_address => ids of the folders the user owns or has been given access to
+pub static USER_FOLDERS: Keymap<Addr, Vec<[u8; 32]>> = Keymap::new(KEY_USER_FOLDERS);
+
+/// (source_chain, source_address) => allowed to send messages through Axelar
+pub static ALLOWED_SOURCES: Keymap<(String, String), bool> = Keymap::new(KEY_ALLOWED_SOURCES);
+
+/// relayer address => allowed to relay messages to the contract
+pub static ALLOWED_RELAYERS: Keymap<Addr, bool> = Keymap::new(KEY_ALLOWED_RELAYERS);
+
+/// sha256(public_key | encrypted payload) => deadline of the message already executed, if any
+pub static PROCESSED_MESSAGES: Keymap<[u8; 32], Option<Expiration>> = Keymap::new(KEY_PROCESSED_MESSAGES);
+
+/// user_address => names of the permits revoked by the user
+pub static USER_REVOKED_PERMITS: Keymap<Addr, Vec<String>> = Keymap::new(KEY_USER_REVOKED_PERMITS);
+
+/// user_address => Ethereum address of the key that signed the last permit executed by the user
+pub static ETHEREUM_ADDRESSES: Keymap<Addr, Addr> = Keymap::new(KEY_ETHEREUM_ADDRESSES);
 
 
 #[derive(Serialize, Debug, Deserialize, Clone, JsonSchema)]
@@ -35,6 +154,17 @@ pub struct Config {
     // the address of this contract, used to validate query permits
     pub contract_address: Addr,
     pub index: u128,
+    // the admin can pause the contract in case of emergency
+    pub admin: Addr,
+    pub writes_paused: bool,
+    pub reads_paused: bool,
+}
+
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct LegacyConfig {
+    pub contract_address: Addr,
+    pub index: u128,
 }
 
 
@@ -42,17 +172,161 @@ pub struct Config {
 pub struct ContractKeys {
     pub private_key: Vec<u8>,
     pub public_key: Vec<u8>,
+    pub version: u32, // Incremented at each rotation of the keys
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct LegacyContractKeys {
+    pub private_key: Vec<u8>,
+    pub public_key: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct PreviousContractKeys {
+    pub keys: ContractKeys,
+    pub expiration: Expiration, // End of the grace period
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
 pub struct FileState {
     pub payload: String, 
+    #[serde(default)]
+    pub version: u32, // Latest version of the file, see FILE_VERSIONS
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct FileVersion {
+    pub payload: String,
+    pub author: Addr,
+    pub created_at: Option<Timestamp>, // Unknown for a file stored before the versioning
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
 pub struct FileMetadata {
     pub owner: Addr, 
     pub viewers: Vec<Addr>,
+    #[serde(default)]
+    pub groups: Vec<[u8; 32]>, // Groups having a viewing access
+    #[serde(default)]
+    pub folder: Option<[u8; 32]>, // Folder containing the file, if any
+    #[serde(default)]
+    pub pending_rekey: bool, // The payload is not served until the owner replaces it
+    #[serde(default)]
+    pub rotated_at: Option<Timestamp>, // Last time the payload was replaced with a new key
+    #[serde(default)]
+    pub info: FileInfo,
+    #[serde(default)]
+    pub created_at: Option<Timestamp>,
+    #[serde(default)]
+    pub updated_at: Option<Timestamp>, // Last update of the content or of the information
+}
+
+
+/// Information given by the owner of a file, readable without the payload.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, JsonSchema)]
+pub struct FileInfo {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub mime_type: Option<String>,
+    #[serde(default)]
+    pub size: Option<u64>, // Size in bytes of the plaintext
+    #[serde(default)]
+    pub sha256: Option<String>, // Hex encoded SHA-256 of the plaintext
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+
+/// Role of a user on a file.
+///
+/// Roles are ordered: each role has all the rights of the roles below it.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FileRole {
+    Viewer,  // Can see the file
+    Editor,  // Can update the file
+    Manager, // Can manage the viewers and editors of the file
+    Owner,   // Can do anything, including changing the owner
+}
+
+
+/// Expiration of an access, either at a given block height or block time.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Expiration {
+    AtHeight(u64),
+    AtTime(Timestamp),
+}
+
+impl Expiration {
+    pub fn is_expired(&self, block: &BlockInfo) -> bool {
+        match self {
+            Expiration::AtHeight(height) => block.height >= *height,
+            Expiration::AtTime(time) => block.time >= *time,
+        }
+    }
+}
+
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct FilePermission {
+    pub role: FileRole,
+    pub expires_at: Option<Expiration>, // None if the access never expires
+}
+
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessRequestStatus {
+    Pending,
+    Approved,
+    Denied,
+}
+
+
+/// Request of a user to access a file, answered by the owner or a manager.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct AccessRequest {
+    pub note: String,
+    pub status: AccessRequestStatus,
+    pub requested_at: Timestamp,
+}
+
+
+/// Hash-locked invitation to a file. Anyone knowing the preimage of the hash
+/// can claim a viewing access to the file.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct ClaimCode {
+    pub file_key: [u8; 32],
+    pub max_claims: Option<u32>, // None if the code can be claimed without limit
+    pub claims: u32,
+    pub expires_at: Option<Expiration>,
+}
+
+
+/// A named set of users, allowing to share files with all of them at once.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct Group {
+    pub name: String,
+    pub owner: Addr, // Only the owner can manage the members
+    pub members: Vec<Addr>,
+    pub files: Vec<[u8; 32]>, // Files shared with the group
+}
+
+
+/// A folder containing files and other folders.
+///
+/// The viewers of a folder can see everything inside it, including the
+/// content of its sub-folders.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct Folder {
+    pub name: String,
+    pub owner: Addr, // Only the owner can manage the folder
+    pub viewers: Vec<Addr>,
+    pub parent: Option<[u8; 32]>, // None for a root folder
+    pub files: Vec<[u8; 32]>,
+    pub folders: Vec<[u8; 32]>, // Sub-folders
 }
 
 